@@ -0,0 +1,50 @@
+//! Detect the functions of libsmbclient which are missing from its older versions.
+//!
+//! pavao-sys doesn't bind them, so they are declared by the crate; each declaration is compiled only
+//! when `libsmbclient.h` declares the function too, and the features relying on it are rejected otherwise.
+
+use std::path::PathBuf;
+
+/// Optional functions of libsmbclient, along with the cfg set when they are declared
+const OPTIONAL_FUNCTIONS: &[(&str, &str)] = &[
+    ("smbc_setOptionPosixExtensions", "smbc_posix_extensions"),
+    ("smbc_setConfiguration", "smbc_configuration"),
+    ("smbc_setOptionProtocols", "smbc_protocols"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    for (_, cfg) in OPTIONAL_FUNCTIONS {
+        println!("cargo:rustc-check-cfg=cfg({cfg})");
+    }
+    if std::env::var("CARGO_CFG_TARGET_FAMILY").as_deref() != Ok("unix") {
+        return;
+    }
+    let Some(header) = find_header() else {
+        println!("cargo:warning=libsmbclient.h not found; the optional libsmbclient features are disabled");
+        return;
+    };
+    println!("cargo:rerun-if-changed={}", header.display());
+    let declarations = std::fs::read_to_string(&header).unwrap_or_default();
+    for (function, cfg) in OPTIONAL_FUNCTIONS {
+        if declarations.contains(&format!("{function}(")) {
+            println!("cargo:rustc-cfg={cfg}");
+        }
+    }
+}
+
+/// Find `libsmbclient.h` in the include paths of the `smbclient` pkg-config package, then in the system ones
+fn find_header() -> Option<PathBuf> {
+    let include_paths = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .probe("smbclient")
+        .map(|library| library.include_paths)
+        .unwrap_or_default();
+    include_paths
+        .into_iter()
+        .chain(["/usr/include/samba-4.0", "/usr/local/include/samba-4.0"].map(PathBuf::from))
+        .chain(["/usr/include", "/usr/local/include"].map(PathBuf::from))
+        .map(|dir| dir.join("libsmbclient.h"))
+        .find(|header| header.is_file())
+}
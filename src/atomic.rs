@@ -0,0 +1,87 @@
+//! # atomic
+//!
+//! Naming of temporary files used by atomic writes
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes temporary files created by the same process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Naming scheme for the temporary files used by atomic writes.
+///
+/// The temporary file is created in the same directory of the target and named
+/// `{prefix}{file_name}.{pid}-{n}{suffix}`. By default the prefix is `.`, so the file is hidden
+/// to most clients, and the suffix is `.part`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempNaming {
+    prefix: String,
+    suffix: String,
+}
+
+impl Default for TempNaming {
+    fn default() -> Self {
+        Self {
+            prefix: String::from("."),
+            suffix: String::from(".part"),
+        }
+    }
+}
+
+impl TempNaming {
+    /// Set the prefix of the temporary file name
+    pub fn prefix<S: AsRef<str>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.as_ref().to_string();
+        self
+    }
+
+    /// Set the suffix of the temporary file name
+    pub fn suffix<S: AsRef<str>>(mut self, suffix: S) -> Self {
+        self.suffix = suffix.as_ref().to_string();
+        self
+    }
+
+    /// Get a new temporary path for `target`
+    pub(crate) fn temp_path(&self, target: &Path) -> PathBuf {
+        let file_name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        target.with_file_name(format!(
+            "{}{}.{}-{}{}",
+            self.prefix,
+            file_name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            self.suffix
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_name_temp_file_in_target_directory() {
+        let temp = TempNaming::default().temp_path(Path::new("/share/dir/a.txt"));
+        assert_eq!(temp.parent().unwrap(), Path::new("/share/dir"));
+        let name = temp.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with(".a.txt."));
+        assert!(name.ends_with(".part"));
+    }
+
+    #[test]
+    fn should_name_temp_file_with_custom_scheme() {
+        let naming = TempNaming::default().prefix("~").suffix(".tmp");
+        let first = naming.temp_path(Path::new("a.txt"));
+        let second = naming.temp_path(Path::new("a.txt"));
+        assert_ne!(first, second);
+        let name = first.to_string_lossy().to_string();
+        assert!(name.starts_with("~a.txt."));
+        assert!(name.ends_with(".tmp"));
+    }
+}
@@ -0,0 +1,518 @@
+//! # cache
+//!
+//! TTL based metadata cache and content cache for `SmbFs`
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use remotefs::fs::File;
+
+/// Cache for the metadata returned by `stat` and `list_dir`.
+///
+/// Entries are served for `ttl` after being fetched; a directory listed less than `ttl` ago
+/// also answers `exists` for its children without contacting the server.
+/// The cache is invalidated by the mutations performed through the client which owns it;
+/// changes made by other clients can be reported with [`MetadataCache::invalidate`],
+/// for instance from a change notification watcher holding a clone of the cache.
+///
+/// The cache is disabled (`ttl` is `None`) by default.
+#[derive(Debug, Clone, Default)]
+pub struct MetadataCache {
+    inner: Arc<Mutex<Entries>>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    ttl: Option<Duration>,
+    files: HashMap<PathBuf, (Instant, File)>,
+    listings: HashMap<PathBuf, (Instant, Vec<File>)>,
+}
+
+impl Entries {
+    fn is_fresh(&self, fetched_at: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| fetched_at.elapsed() < ttl)
+    }
+}
+
+impl MetadataCache {
+    /// Create a cache keeping entries for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        let cache = Self::default();
+        cache.set_ttl(Some(ttl));
+        cache
+    }
+
+    /// Change the time to live of the entries. `None` disables the cache and drops its entries
+    pub fn set_ttl(&self, ttl: Option<Duration>) {
+        let mut entries = self.inner.lock().unwrap();
+        entries.ttl = ttl;
+        if ttl.is_none() {
+            entries.files.clear();
+            entries.listings.clear();
+        }
+    }
+
+    /// Get the time to live of the entries
+    pub fn ttl(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().ttl
+    }
+
+    /// Drop the entries for `path`, its children and the listing of its parent
+    pub fn invalidate(&self, path: &Path) {
+        let mut entries = self.inner.lock().unwrap();
+        entries.files.retain(|p, _| !p.starts_with(path));
+        entries.listings.retain(|p, _| !p.starts_with(path));
+        if let Some(parent) = path.parent() {
+            entries.files.remove(parent);
+            entries.listings.remove(parent);
+        }
+    }
+
+    /// Drop all the entries
+    pub fn clear(&self) {
+        let mut entries = self.inner.lock().unwrap();
+        entries.files.clear();
+        entries.listings.clear();
+    }
+
+    /// Get the cached metadata for `path`
+    pub(crate) fn get(&self, path: &Path) -> Option<File> {
+        let entries = self.inner.lock().unwrap();
+        entries
+            .files
+            .get(path)
+            .filter(|(fetched_at, _)| entries.is_fresh(*fetched_at))
+            .map(|(_, file)| file.clone())
+    }
+
+    /// Tell whether `path` exists, if it is known to the cache
+    pub(crate) fn exists(&self, path: &Path) -> Option<bool> {
+        let entries = self.inner.lock().unwrap();
+        if let Some((fetched_at, _)) = entries.files.get(path) {
+            if entries.is_fresh(*fetched_at) {
+                return Some(true);
+            }
+        }
+        let (fetched_at, children) = entries.listings.get(path.parent()?)?;
+        entries
+            .is_fresh(*fetched_at)
+            .then(|| children.iter().any(|child| child.path() == path))
+    }
+
+    /// Get the cached listing of `dir`
+    pub(crate) fn list(&self, dir: &Path) -> Option<Vec<File>> {
+        let entries = self.inner.lock().unwrap();
+        entries
+            .listings
+            .get(dir)
+            .filter(|(fetched_at, _)| entries.is_fresh(*fetched_at))
+            .map(|(_, children)| children.clone())
+    }
+
+    /// Store the metadata of `file`
+    pub(crate) fn insert(&self, file: &File) {
+        let mut entries = self.inner.lock().unwrap();
+        if entries.ttl.is_some() {
+            entries
+                .files
+                .insert(file.path().to_path_buf(), (Instant::now(), file.clone()));
+        }
+    }
+
+    /// Store the listing of `dir`, along with the metadata of its children.
+    ///
+    /// The symlinks are listed without being followed, so they don't stand for the metadata of their target
+    pub(crate) fn insert_listing(&self, dir: &Path, children: &[File]) {
+        let mut entries = self.inner.lock().unwrap();
+        if entries.ttl.is_some() {
+            let now = Instant::now();
+            for child in children.iter().filter(|child| !child.is_symlink()) {
+                entries
+                    .files
+                    .insert(child.path().to_path_buf(), (now, child.clone()));
+            }
+            entries
+                .listings
+                .insert(dir.to_path_buf(), (now, children.to_vec()));
+        }
+    }
+}
+
+/// Cache for the content of the files read with `open_file`, bounded to `capacity` bytes,
+/// holding only the files up to `max_file_size` bytes.
+///
+/// The cache is never told when a cached content becomes stale, since no lease is requested on
+/// the files: each entry is kept along with the [`ContentVersion`] of the file and is served only
+/// as long as it still matches. The oldest entries are evicted first.
+///
+/// The cache is disabled (`capacity` is 0) by default.
+#[derive(Debug, Default)]
+pub(crate) struct DataCache {
+    inner: Mutex<Contents>,
+}
+
+#[derive(Debug, Default)]
+struct Contents {
+    capacity: usize,
+    max_file_size: usize,
+    size: usize,
+    files: HashMap<PathBuf, Content>,
+    order: VecDeque<PathBuf>,
+}
+
+#[derive(Debug)]
+struct Content {
+    version: ContentVersion,
+    data: Arc<[u8]>,
+}
+
+/// Attributes of a file which tell whether its cached content is still current.
+///
+/// Besides the size and the modification time, which the writer can set back, the change time
+/// and the file ID are compared when the backend reports them: the former is updated by the
+/// server on every write and can't be set by the clients, the latter tells a file replaced by
+/// another one under the same path. A rewrite within the granularity of the timestamps of the
+/// server, keeping the same size, still goes unnoticed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ContentVersion {
+    size: u64,
+    modified: SystemTime,
+    changed: Option<SystemTime>,
+    file_id: Option<u64>,
+}
+
+impl ContentVersion {
+    /// Make the version of a file of `size` bytes, last modified at `modified`
+    pub fn new(size: u64, modified: SystemTime) -> Self {
+        Self {
+            size,
+            modified,
+            changed: None,
+            file_id: None,
+        }
+    }
+
+    /// Get the size of the file
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Set the time of the last change of the file, data or attributes
+    pub fn changed(mut self, changed: Option<SystemTime>) -> Self {
+        self.changed = changed;
+        self
+    }
+
+    /// Set the ID of the file on its volume
+    pub fn file_id(mut self, file_id: Option<u64>) -> Self {
+        self.file_id = file_id;
+        self
+    }
+}
+
+impl Contents {
+    fn remove(&mut self, path: &Path) {
+        if let Some(content) = self.files.remove(path) {
+            self.size -= content.data.len();
+            self.order.retain(|p| p != path);
+        }
+    }
+
+    /// Evict the oldest entries until `size` bytes fit in the cache
+    fn make_room(&mut self, size: usize) {
+        while self.size + size > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(content) = self.files.remove(&oldest) {
+                self.size -= content.data.len();
+            }
+        }
+    }
+}
+
+impl DataCache {
+    /// Change the amount of bytes the cache can hold and the size of the largest file it keeps,
+    /// evicting the oldest entries as needed
+    pub fn set_limits(&self, capacity: usize, max_file_size: usize) {
+        let mut contents = self.inner.lock().unwrap();
+        contents.capacity = capacity;
+        contents.max_file_size = max_file_size;
+        contents.make_room(0);
+    }
+
+    /// Get the amount of bytes the cache can hold
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().unwrap().capacity
+    }
+
+    /// Get the size of the largest file the cache keeps
+    pub fn max_file_size(&self) -> usize {
+        let contents = self.inner.lock().unwrap();
+        contents.max_file_size.min(contents.capacity)
+    }
+
+    /// Get the cached content of `path`, if it was stored with the same `version`
+    pub fn get(&self, path: &Path, version: &ContentVersion) -> Option<Arc<[u8]>> {
+        let mut contents = self.inner.lock().unwrap();
+        let content = contents.files.get(path)?;
+        if content.version == *version && content.data.len() as u64 == version.size {
+            return Some(content.data.clone());
+        }
+        contents.remove(path);
+        None
+    }
+
+    /// Store the content of `path`, unless it is larger than `max_file_size`
+    pub fn insert(&self, path: &Path, version: ContentVersion, data: Arc<[u8]>) {
+        let mut contents = self.inner.lock().unwrap();
+        contents.remove(path);
+        if data.len() > contents.max_file_size.min(contents.capacity) {
+            return;
+        }
+        contents.make_room(data.len());
+        contents.size += data.len();
+        contents.order.push_back(path.to_path_buf());
+        contents
+            .files
+            .insert(path.to_path_buf(), Content { version, data });
+    }
+
+    /// Drop the content of `path` and of its children
+    pub fn invalidate(&self, path: &Path) {
+        let mut contents = self.inner.lock().unwrap();
+        let stale: Vec<PathBuf> = contents
+            .files
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in stale {
+            contents.remove(&p);
+        }
+    }
+}
+
+/// Writer which copies the bytes written to `inner`, to fill the [`DataCache`] while reading
+/// a file. The copy is dropped once more than `limit` bytes have been written
+pub(crate) struct Tee<W> {
+    inner: W,
+    data: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl<W> Tee<W>
+where
+    W: Write,
+{
+    pub fn new(inner: W, limit: usize) -> Self {
+        Self {
+            inner,
+            data: Some(Vec::new()),
+            limit,
+        }
+    }
+
+    /// Get the bytes written, unless there are more than `limit`
+    pub fn into_data(self) -> Option<Vec<u8>> {
+        self.data
+    }
+}
+
+impl<W> Write for Tee<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(data) = self.data.as_mut() {
+            if data.len() + n > self.limit {
+                self.data = None;
+            } else {
+                data.extend_from_slice(&buf[..n]);
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+    use remotefs::fs::{FileType, Metadata};
+
+    use super::*;
+
+    fn version(size: u64) -> ContentVersion {
+        ContentVersion::new(size, SystemTime::UNIX_EPOCH)
+    }
+
+    fn file(path: &str) -> File {
+        File {
+            path: PathBuf::from(path),
+            metadata: Metadata::default(),
+        }
+    }
+
+    #[test]
+    fn should_not_cache_when_disabled() {
+        let cache = MetadataCache::default();
+        cache.insert(&file("/a.txt"));
+        assert!(cache.get(Path::new("/a.txt")).is_none());
+        assert!(cache.exists(Path::new("/a.txt")).is_none());
+    }
+
+    #[test]
+    fn should_serve_stat_and_exists_from_listing() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        cache.insert_listing(Path::new("/dir"), &[file("/dir/a.txt"), file("/dir/b.txt")]);
+        assert_eq!(
+            cache.get(Path::new("/dir/a.txt")).unwrap().path(),
+            Path::new("/dir/a.txt")
+        );
+        assert_eq!(cache.exists(Path::new("/dir/b.txt")), Some(true));
+        assert_eq!(cache.exists(Path::new("/dir/c.txt")), Some(false));
+        assert_eq!(cache.exists(Path::new("/other/c.txt")), None);
+        assert_eq!(cache.list(Path::new("/dir")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn should_not_serve_stat_of_symlinks_from_listing() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        let link = File {
+            path: PathBuf::from("/dir/link"),
+            metadata: Metadata::default()
+                .file_type(FileType::Symlink)
+                .symlink("/dir/a.txt"),
+        };
+        cache.insert_listing(Path::new("/dir"), &[file("/dir/a.txt"), link]);
+        assert!(cache.get(Path::new("/dir/link")).is_none());
+        assert_eq!(cache.exists(Path::new("/dir/link")), Some(true));
+        assert_eq!(cache.list(Path::new("/dir")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn should_expire_entries() {
+        let cache = MetadataCache::new(Duration::ZERO);
+        cache.insert_listing(Path::new("/dir"), &[file("/dir/a.txt")]);
+        assert!(cache.get(Path::new("/dir/a.txt")).is_none());
+        assert!(cache.exists(Path::new("/dir/c.txt")).is_none());
+        assert!(cache.list(Path::new("/dir")).is_none());
+    }
+
+    #[test]
+    fn should_invalidate_path_children_and_parent_listing() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        cache.insert_listing(Path::new("/dir"), &[file("/dir/sub")]);
+        cache.insert_listing(Path::new("/dir/sub"), &[file("/dir/sub/a.txt")]);
+        cache.invalidate(Path::new("/dir/sub"));
+        assert!(cache.get(Path::new("/dir/sub")).is_none());
+        assert!(cache.get(Path::new("/dir/sub/a.txt")).is_none());
+        assert!(cache.list(Path::new("/dir/sub")).is_none());
+        assert!(cache.list(Path::new("/dir")).is_none());
+        assert!(cache.exists(Path::new("/dir/sub")).is_none());
+    }
+
+    #[test]
+    fn should_serve_content_while_unchanged() {
+        let cache = DataCache::default();
+        cache.set_limits(16, 16);
+        cache.insert(Path::new("/a.txt"), version(5), Arc::from(&b"hello"[..]));
+        assert_eq!(
+            cache.get(Path::new("/a.txt"), &version(5)).as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert!(cache.get(Path::new("/a.txt"), &version(6)).is_none());
+        // stale entries are dropped
+        assert!(cache.get(Path::new("/a.txt"), &version(5)).is_none());
+        cache.insert(Path::new("/a.txt"), version(5), Arc::from(&b"hello"[..]));
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        assert!(cache
+            .get(Path::new("/a.txt"), &ContentVersion::new(5, modified))
+            .is_none());
+    }
+
+    #[test]
+    fn should_not_serve_content_of_changed_file() {
+        let cache = DataCache::default();
+        cache.set_limits(16, 16);
+        let changed = Some(SystemTime::UNIX_EPOCH);
+        let stored = version(5).changed(changed).file_id(Some(1));
+        cache.insert(Path::new("/a.txt"), stored, Arc::from(&b"hello"[..]));
+        assert!(cache.get(Path::new("/a.txt"), &stored).is_some());
+        // same size and modification time, but changed since
+        let rewritten = stored.changed(Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1)));
+        assert!(cache.get(Path::new("/a.txt"), &rewritten).is_none());
+        // replaced by another file
+        cache.insert(Path::new("/a.txt"), stored, Arc::from(&b"hello"[..]));
+        assert!(cache
+            .get(Path::new("/a.txt"), &stored.file_id(Some(2)))
+            .is_none());
+    }
+
+    #[test]
+    fn should_evict_oldest_content() {
+        let cache = DataCache::default();
+        cache.set_limits(8, 8);
+        cache.insert(Path::new("/a.txt"), version(4), Arc::from(&b"aaaa"[..]));
+        cache.insert(Path::new("/b.txt"), version(4), Arc::from(&b"bbbb"[..]));
+        cache.insert(Path::new("/c.txt"), version(2), Arc::from(&b"cc"[..]));
+        assert!(cache.get(Path::new("/a.txt"), &version(4)).is_none());
+        assert!(cache.get(Path::new("/b.txt"), &version(4)).is_some());
+        assert!(cache.get(Path::new("/c.txt"), &version(2)).is_some());
+        // larger than the cache
+        cache.insert(Path::new("/d.txt"), version(9), Arc::from(&[0; 9][..]));
+        assert!(cache.get(Path::new("/d.txt"), &version(9)).is_none());
+        cache.set_limits(0, 8);
+        assert!(cache.get(Path::new("/b.txt"), &version(4)).is_none());
+    }
+
+    #[test]
+    fn should_not_cache_files_larger_than_limit() {
+        let cache = DataCache::default();
+        cache.set_limits(16, 4);
+        assert_eq!(cache.max_file_size(), 4);
+        cache.insert(Path::new("/a.txt"), version(5), Arc::from(&b"hello"[..]));
+        assert!(cache.get(Path::new("/a.txt"), &version(5)).is_none());
+        cache.insert(Path::new("/b.txt"), version(4), Arc::from(&b"hell"[..]));
+        assert!(cache.get(Path::new("/b.txt"), &version(4)).is_some());
+        // the capacity bounds the entries too
+        cache.set_limits(2, 4);
+        assert_eq!(cache.max_file_size(), 2);
+    }
+
+    #[test]
+    fn should_copy_bytes_up_to_limit() {
+        let mut dest = Vec::new();
+        let mut tee = Tee::new(&mut dest, 4);
+        tee.write_all(b"ab").unwrap();
+        tee.write_all(b"cd").unwrap();
+        assert_eq!(tee.into_data().as_deref(), Some(&b"abcd"[..]));
+        let mut tee = Tee::new(&mut dest, 4);
+        tee.write_all(b"abc").unwrap();
+        tee.write_all(b"de").unwrap();
+        // the writer still gets all the bytes
+        tee.write_all(b"f").unwrap();
+        assert!(tee.into_data().is_none());
+        assert_eq!(dest, b"abcdabcdef");
+    }
+
+    #[test]
+    fn should_invalidate_content_of_children() {
+        let cache = DataCache::default();
+        cache.set_limits(16, 16);
+        cache.insert(Path::new("/dir/a.txt"), version(1), Arc::from(&b"a"[..]));
+        cache.insert(Path::new("/other.txt"), version(1), Arc::from(&b"b"[..]));
+        cache.invalidate(Path::new("/dir"));
+        assert!(cache.get(Path::new("/dir/a.txt"), &version(1)).is_none());
+        assert!(cache.get(Path::new("/other.txt"), &version(1)).is_some());
+    }
+}
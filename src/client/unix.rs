@@ -2,22 +2,59 @@
 //!
 //! UNIX implementation of Smb fs client
 
+mod context;
+mod credentials;
+mod signing;
+
 // -- exports
-use std::io::{self, Read, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use libc::mode_t;
-pub use pavao::{SmbClient, SmbCredentials, SmbEncryptionLevel, SmbOptions, SmbShareMode};
-use pavao::{SmbDirentType, SmbMode, SmbOpenOptions};
-use remotefs::fs::{File, Metadata, ReadStream, UnixPex, Welcome, WriteStream};
+pub use pavao::{SmbClient, SmbEncryptionLevel, SmbOptions, SmbShareMode};
+use pavao::{SmbDirentInfo, SmbDirentType, SmbError, SmbFile, SmbMode, SmbOpenOptions, SmbResult};
+use remotefs::fs::{File, FileType, Metadata, ReadStream, UnixPex, Welcome, WriteStream};
 use remotefs::{RemoteError, RemoteErrorType, RemoteFs, RemoteResult};
 
+use crate::atomic::TempNaming;
+use crate::cache::{DataCache, MetadataCache, Tee};
+use crate::dfs::SmbDfsTarget;
+use crate::dialect::SmbDialect;
+use crate::error::{self, RemoveFailures, StatusCode};
+#[cfg(feature = "find")]
+use crate::find::{Find, FindOptions, Walk};
+use crate::info::SmbFileInfo;
+use crate::liveness::Liveness;
+use crate::transfer::{
+    self, CancellationToken, Progress, ProgressCallback, RateLimiter, Reopening, Sparse, Throttled,
+    TransferOptions, TransferProgress,
+};
 use crate::utils::{path as path_utils, smb as smb_utils};
 
+pub use self::context::SmbContextOptions;
+pub use self::credentials::SmbCredentials;
+pub use self::signing::SmbSigning;
+
 /// SMB file system client
 pub struct SmbFs {
     client: SmbClient,
+    /// URI of the share, for the requests made directly on the libsmbclient context
+    share_uri: String,
     wrkdir: PathBuf,
+    transfer: TransferOptions,
+    atomic: Option<TempNaming>,
+    on_progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+    limiter: RateLimiter,
+    cache: MetadataCache,
+    data_cache: DataCache,
+    liveness: Liveness,
+    force_readonly: bool,
+    multi_share: bool,
+    encryption: Option<SmbEncryptionLevel>,
 }
 
 impl SmbFs {
@@ -25,12 +62,309 @@ impl SmbFs {
     /// Fails if it is not possible to instantiate a smb context.
     pub fn try_new(credentials: SmbCredentials, options: SmbOptions) -> RemoteResult<Self> {
         Ok(Self {
-            client: SmbClient::new(credentials, options)
-                .map_err(|e| RemoteError::new_ex(RemoteErrorType::BadAddress, e))?,
+            share_uri: credentials.share_uri(),
+            client: SmbClient::new(credentials.into(), options)
+                .map_err(|e| error::remote_error(e, RemoteErrorType::BadAddress))?,
             wrkdir: PathBuf::from("/"),
+            transfer: TransferOptions::default(),
+            atomic: None,
+            on_progress: None,
+            cancellation: CancellationToken::default(),
+            limiter: RateLimiter::default(),
+            cache: MetadataCache::default(),
+            data_cache: DataCache::default(),
+            liveness: Liveness::default(),
+            force_readonly: false,
+            multi_share: false,
+            encryption: None,
         })
     }
 
+    /// Set the options used by `open_file`, `create_file` and `append_file`.
+    ///
+    /// Each chunk is handed to libsmbclient as a single read/write on the same handle, one after
+    /// the other, so there is never more than one request in flight: `TransferOptions::concurrency`
+    /// doesn't apply. `TransferOptions::read_ahead` only sets how many chunks the local side
+    /// buffers ahead.
+    pub fn transfer_options(mut self, options: TransferOptions) -> Self {
+        self.transfer = options;
+        self
+    }
+
+    /// Make `create_file` write to a temporary file in the same directory, named after `naming`,
+    /// which is then renamed over the target.
+    /// On failure the temporary file is removed and the target is left untouched.
+    ///
+    /// Readers never see a partially written file, but **replacing an existing target is not
+    /// atomic** on UNIX: libsmbclient doesn't rename with replace-if-exists, it unlinks the target
+    /// and then renames the temporary file, so readers may briefly find no file at all, and the
+    /// target is lost if the rename fails after the unlink.
+    pub fn atomic_writes(mut self, naming: Option<TempNaming>) -> Self {
+        self.atomic = naming;
+        self
+    }
+
+    /// Set a callback to be notified about the progress of `open_file`, `create_file`, `append_file`
+    /// and resumed transfers
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&TransferProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Get the token to cancel the running transfer
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Limit the bandwidth used by this client to `rate` bytes per second, with bursts up to `burst` bytes
+    pub fn rate_limit(self, rate: u64, burst: u64) -> Self {
+        self.limiter.set_limit(Some(rate), burst);
+        self
+    }
+
+    /// Get the rate limiter shared by all the transfers and streams of this client,
+    /// which can be used to change the limit at runtime
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.limiter.clone()
+    }
+
+    /// Cache the metadata returned by `stat` and `list_dir` for `ttl`.
+    ///
+    /// While cached, `stat`, `exists` and `list_dir` don't contact the server at all.
+    /// The entries are invalidated by the mutations performed through this client.
+    pub fn cache_metadata(self, ttl: Duration) -> Self {
+        self.cache.set_ttl(Some(ttl));
+        self
+    }
+
+    /// Get the metadata cache of this client, which can be used to invalidate entries
+    /// changed by other clients (e.g. on change notifications)
+    pub fn metadata_cache(&self) -> MetadataCache {
+        self.cache.clone()
+    }
+
+    /// Cache the content of the files read with `open_file`, up to `capacity` bytes in total;
+    /// files larger than `max_file_size` are streamed without being cached.
+    ///
+    /// **This is not a lease-backed cache**: libsmbclient doesn't request leases, so the server
+    /// never tells when a cached content becomes stale. Instead, every `open_file` stats the file
+    /// on the server, bypassing the metadata cache, and serves the cached content only if its size,
+    /// modification and change time and file ID are unchanged. This saves reading small files,
+    /// such as configuration files, over and over, not the round trip of the `stat`.
+    ///
+    /// A file rewritten by another client with the same size within the granularity of the
+    /// timestamps of the server file system (e.g. 2 seconds on FAT) is served stale.
+    pub fn cache_data(self, capacity: usize, max_file_size: usize) -> Self {
+        self.data_cache.set_limits(capacity, max_file_size);
+        self
+    }
+
+    /// Set the interval after which an idle connection is probed before being used (60 seconds by default).
+    ///
+    /// The connection is seen alive whenever an operation succeeds, and lost when one fails with a
+    /// connection error. The first operation after it has been lost, or idle for longer than `interval`,
+    /// is preceded by a probe (a `stat` of the root of the share), so that it fails early with `ConnectionError`.
+    /// Nothing keeps the connection alive while idle. `None` disables the probes of idle connections.
+    pub fn idle_probe_interval(mut self, interval: Option<Duration>) -> Self {
+        self.liveness.set_idle_probe(interval);
+        self
+    }
+
+    /// Search `dir` recursively for the entries matching `options`, reporting them as they are found
+    #[cfg(feature = "find")]
+    pub fn find_iter(&mut self, dir: &Path, options: FindOptions) -> Find<'_> {
+        Find::new(self, dir, options)
+    }
+
+    /// Iterate over the entries of the directory at `path`, in the order they are returned by the server.
+    ///
+    /// Unlike `list_dir`, the entries are not stat'd one by one: the directory is kept open and each
+    /// call to `next` reads one entry, whose metadata comes from the listing itself. The directory
+    /// is closed when the iterator is dropped, so stopping early saves decoding the other entries;
+    /// libsmbclient fetches the whole listing from the server when the directory is opened, though,
+    /// so memory usage is bound by its size on the C side.
+    pub fn read_dir(&mut self, path: &Path) -> RemoteResult<ReadDir<'_>> {
+        self.check_connection()?;
+        let path = self.get_uri(path);
+        trace!("reading directory {}", path);
+        if self.is_server_root(&path) {
+            // libsmbclient doesn't report the metadata of shares along with their names
+            let shares = self.list_dir(Path::new(&path))?;
+            return Ok(ReadDir {
+                dir: path,
+                entries: Entries::Files(shares.into_iter()),
+            });
+        }
+        let entries =
+            smb_utils::Directory::open(&self.client, &format!("{}{}", self.share_uri, path))
+                .map(Entries::Directory)
+                .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))?;
+        self.liveness.seen();
+        Ok(ReadDir { dir: path, entries })
+    }
+
+    /// Address all the shares of the server as the top level directories of the paths (`/share/path`),
+    /// instead of the share of the credentials, which must then be empty. Listing `/` returns the shares.
+    ///
+    /// With `SmbOptions::one_share_per_server`, all the shares are accessed through the same session,
+    /// switching tree connects as needed; otherwise libsmbclient authenticates a connection per share.
+    pub fn multi_share(mut self, enable: bool) -> Self {
+        self.multi_share = enable;
+        self
+    }
+
+    /// Get the target of the symlink at `path`.
+    ///
+    /// libsmbclient reports symlinks through the mode bits when the server grants the POSIX
+    /// extensions (see [`SmbContextOptions::posix_extensions`]), but has no way to read their target, so this fails with `UnsupportedFeature`
+    /// for them, and with `BadFile` for any other entry.
+    pub fn read_link(&mut self, path: &Path) -> RemoteResult<PathBuf> {
+        let file = self.stat(path)?;
+        match file.is_symlink() {
+            true => Err(RemoteError::new_ex(
+                RemoteErrorType::UnsupportedFeature,
+                "libsmbclient can't read the target of symlinks",
+            )),
+            false => Err(RemoteError::new_ex(
+                RemoteErrorType::BadFile,
+                format!("{} is not a symlink", file.path().display()),
+            )),
+        }
+    }
+
+    /// Create a hard link at `new` to the file at `existing`.
+    ///
+    /// libsmbclient can't create hard links, so this always fails with `UnsupportedFeature`
+    pub fn hard_link(&mut self, _existing: &Path, _new: &Path) -> RemoteResult<()> {
+        Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            "libsmbclient can't create hard links",
+        ))
+    }
+
+    /// Get the targets of the DFS link `path` crosses.
+    ///
+    /// libsmbclient follows the DFS referrals by itself, trying their targets in order with the same
+    /// credentials, but neither exposes them nor lets their caching or the failover be controlled,
+    /// so this always fails with `UnsupportedFeature`
+    pub fn dfs_targets(&mut self, _path: &Path) -> RemoteResult<Vec<SmbDfsTarget>> {
+        Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            "libsmbclient doesn't expose the DFS referrals",
+        ))
+    }
+
+    /// Mark the file at `path` as sparse, or as not sparse, through FSCTL_SET_SPARSE.
+    ///
+    /// libsmbclient can't send FSCTLs, so this always fails with `UnsupportedFeature`
+    pub fn set_sparse(&mut self, _path: &Path, _sparse: bool) -> RemoteResult<()> {
+        Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            "libsmbclient can't mark files as sparse",
+        ))
+    }
+
+    /// Zero `len` bytes of the file at `path` from `offset`, deallocating them if the file is sparse,
+    /// through FSCTL_SET_ZERO_DATA.
+    ///
+    /// libsmbclient can't send FSCTLs, so this always fails with `UnsupportedFeature`
+    pub fn punch_hole(&mut self, _path: &Path, _offset: u64, _len: u64) -> RemoteResult<()> {
+        Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            "libsmbclient can't punch holes",
+        ))
+    }
+
+    /// Get the ranges of the file at `path` which are allocated on disk,
+    /// through FSCTL_QUERY_ALLOCATED_RANGES.
+    ///
+    /// libsmbclient can't send FSCTLs, so this always fails with `UnsupportedFeature`
+    pub fn allocated_ranges(&mut self, _path: &Path) -> RemoteResult<Vec<Range<u64>>> {
+        Err(RemoteError::new_ex(
+            RemoteErrorType::UnsupportedFeature,
+            "libsmbclient can't query the allocated ranges",
+        ))
+    }
+
+    /// Get the SMB specific metadata of the entry at `path`.
+    ///
+    /// libsmbclient reports the link count and a 64-bit file ID, which is the one assigned by the
+    /// server (FileInternalInformation) unless the server doesn't report it, in which case it is a
+    /// hash of the path, so it's neither kept across renames nor unique. `volume_serial` is not the
+    /// serial of the volume either: libsmbclient derives it out of the names of the server and the
+    /// share, so it only tells whether two entries are on the same share. The 128-bit file ID is
+    /// never reported.
+    #[allow(clippy::unnecessary_cast)] // the types of the `stat` fields differ across platforms
+    pub fn file_info(&mut self, path: &Path) -> RemoteResult<SmbFileInfo> {
+        self.check_connection()?;
+        let path = self.get_uri(path);
+        trace!("get file info for {}", path);
+        smb_utils::raw_stat(&self.client, &format!("{}{}", self.share_uri, path))
+            .map(|stat| SmbFileInfo {
+                links: Some(stat.st_nlink as u64),
+                file_id: Some(stat.st_ino as u64),
+                file_id_128: None,
+                volume_serial: Some(stat.st_dev as u64),
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Make `remove_dir_all` clear the read-only attribute of the entries it can't remove because of it
+    pub fn force_remove_readonly(mut self, force: bool) -> Self {
+        self.force_readonly = force;
+        self
+    }
+
+    /// Get the dialect negotiated with the server, if known.
+    ///
+    /// Always `None`: libsmbclient keeps the negotiated dialect to itself. The range of dialects is set
+    /// for the whole process with [`SmbContextOptions::dialects`].
+    pub fn dialect(&self) -> Option<SmbDialect> {
+        None
+    }
+
+    /// Set the encryption level of the session established by `connect`.
+    ///
+    /// `SmbOptions::encryption_level` only applies to the libsmbclient context, which is shared by all
+    /// the clients, when the first client creates it; this sets the level on the context right before
+    /// connecting instead, so it applies to the sessions established afterwards by any client.
+    /// With `SmbEncryptionLevel::Require`, `connect` fails if the session can't be encrypted
+    /// (libsmbclient reports it as `EPERM`, so the error kind is `AuthenticationFailed`).
+    pub fn encryption_level(mut self, level: SmbEncryptionLevel) -> Self {
+        self.encryption = Some(level);
+        self
+    }
+
+    /// Whether the session is encrypted, if known.
+    ///
+    /// Always `None`: libsmbclient reports neither the encryption state of a session nor its cipher.
+    /// The configured level doesn't tell either, since libsmbclient reuses the connections it already
+    /// has to a server, which may have been established unencrypted before the level was required.
+    pub fn is_encrypted(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether the session is signed, if known.
+    ///
+    /// Always `None`: libsmbclient doesn't report whether a session is signed. The policy is set for the
+    /// whole process with [`SmbContextOptions::signing`].
+    pub fn is_signed(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether the server supports SMB3 multichannel, if known.
+    ///
+    /// Always `None`: **multichannel is not supported on UNIX**. libsmbclient reaches each server
+    /// through a single TCP connection, neither querying the interfaces of the server nor binding
+    /// additional channels, and doesn't report the capabilities of the server.
+    pub fn supports_multichannel(&self) -> Option<bool> {
+        None
+    }
+
     /// Return a reference to the inner `pavao::SmbClient`
     pub fn client(&self) -> &SmbClient {
         &self.client
@@ -41,22 +375,367 @@ impl SmbFs {
         &mut self.client
     }
 
+    /// Upload `local` to `remote`, resuming from the size of `remote` if it already exists.
+    ///
+    /// If `remote` is larger than `local` or its tail doesn't match (see [`TransferOptions::verify_tail`]),
+    /// the upload starts over. Returns the amount of bytes written by this call.
+    pub fn resume_upload(&mut self, local: &Path, remote: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let mut source = std::fs::File::open(local)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let source_size = source
+            .metadata()
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?
+            .len();
+        let partial_size = match self.exists(remote)? {
+            true => self.stat(remote)?.metadata.size,
+            false => 0,
+        };
+        let path = self.get_uri(remote);
+        self.invalidate(Path::new(&path));
+        let offset = match partial_size {
+            0 => 0,
+            _ => {
+                let mut partial = self
+                    .client
+                    .open_with(path.as_str(), SmbOpenOptions::default().read(true))
+                    .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+                transfer::resume_offset(
+                    &mut source,
+                    source_size,
+                    &mut partial,
+                    partial_size,
+                    self.transfer.verify_tail,
+                )
+                .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?
+            }
+        };
+        debug!("uploading {} to {} from {}", local.display(), path, offset);
+        let mut file = self
+            .client
+            .open_with(
+                path.as_str(),
+                SmbOpenOptions::default()
+                    .create(true)
+                    .write(true)
+                    .truncate(offset == 0)
+                    .mode(0o644),
+            )
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| source.seek(SeekFrom::Start(offset)))
+            .and_then(|_| {
+                let mut progress = self.progress(Some(source_size - offset));
+                transfer::push(
+                    &mut source,
+                    &mut self.throttled(self.reopening(&path, file, offset, self.transfer.sparse)),
+                    &self.transfer,
+                    &mut progress,
+                )
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Download `remote` to `local`, resuming from the size of `local` if it already exists.
+    ///
+    /// If `local` is larger than `remote` or its tail doesn't match (see [`TransferOptions::verify_tail`]),
+    /// the download starts over. Returns the amount of bytes written by this call.
+    pub fn resume_download(&mut self, remote: &Path, local: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let source_size = self.stat(remote)?.metadata.size;
+        let path = self.get_uri(remote);
+        let mut source = self
+            .client
+            .open_with(path.as_str(), SmbOpenOptions::default().read(true))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let mut partial = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(local)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let offset = partial
+            .metadata()
+            .and_then(|metadata| {
+                transfer::resume_offset(
+                    &mut source,
+                    source_size,
+                    &mut partial,
+                    metadata.len(),
+                    self.transfer.verify_tail,
+                )
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        debug!(
+            "downloading {} to {} from {}",
+            path,
+            local.display(),
+            offset
+        );
+        partial
+            .set_len(offset)
+            .and_then(|_| partial.seek(SeekFrom::Start(offset)))
+            .and_then(|_| source.seek(SeekFrom::Start(offset)))
+            .and_then(|_| {
+                let mut progress = self.progress(Some(source_size - offset));
+                transfer::pull(
+                    &mut self.throttled(&mut source),
+                    &mut partial,
+                    &self.transfer,
+                    &mut progress,
+                )
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
+
     // -- private
 
+    /// Make sure the connection can be used, probing it only if it has been lost or idle for too long
     fn check_connection(&self) -> RemoteResult<()> {
+        if self.liveness.needs_probe() {
+            self.probe()?;
+        }
+        Ok(())
+    }
+
+    /// Probe the connection with a request to the root of the share.
+    /// libsmbclient doesn't expose SMB2 ECHO, so this is the cheapest round trip available
+    fn probe(&self) -> RemoteResult<()> {
         trace!("checking connection...");
-        match self.client.get_user() {
+        // in multi-share mode, the root of the server can only be listed
+        let result = match self.multi_share {
+            true => self.client.list_dir("/").map(drop),
+            false => self.client.stat("/").map(drop),
+        };
+        match result {
             Err(e) => {
                 error!("connection ERROR: {}", e);
-                Err(RemoteError::new_ex(RemoteErrorType::ConnectionError, e))
+                let mut err = error::remote_error(e, RemoteErrorType::ConnectionError);
+                err.kind = match err.kind {
+                    // the server refused the session
+                    RemoteErrorType::PexError | RemoteErrorType::AuthenticationFailed => {
+                        RemoteErrorType::AuthenticationFailed
+                    }
+                    // no dialect in common with the server
+                    RemoteErrorType::UnsupportedFeature => RemoteErrorType::ProtocolError,
+                    _ => RemoteErrorType::ConnectionError,
+                };
+                Err(self.liveness.observe(err))
             }
             Ok(_) => {
                 trace!("connection OK");
+                self.liveness.seen();
                 Ok(())
             }
         }
     }
 
+    /// Remove the directory at `uri` with all its content, listing each directory once.
+    /// Entries which can't be removed are recorded in `failures`
+    fn remove_tree(&self, uri: &str, failures: &mut RemoveFailures) {
+        let entries = match self.client.list_dir(uri) {
+            Ok(entries) => entries,
+            Err(e) => {
+                failures.push(uri, self.remote_error(e, RemoteErrorType::StatFailed));
+                return;
+            }
+        };
+        let failed = failures.len();
+        for entry in entries {
+            let child = format!("{}/{}", uri.trim_end_matches('/'), entry.name());
+            let result = match entry.get_type() {
+                SmbDirentType::Dir => {
+                    self.remove_tree(&child, failures);
+                    continue;
+                }
+                SmbDirentType::File | SmbDirentType::Link => {
+                    self.remove_entry(&child, |client, uri| client.unlink(uri))
+                }
+                _ => continue,
+            };
+            if let Err(err) = result {
+                failures.push(child, err);
+            }
+        }
+        // the directory can't be empty if any of its entries is left
+        if failures.len() == failed {
+            if let Err(err) = self.remove_entry(uri, |client, uri| client.rmdir(uri)) {
+                failures.push(uri, err);
+            }
+        }
+    }
+
+    /// Remove the entry at `uri` with `remove`.
+    /// If forced, the read-only attribute is cleared when the removal is denied, then it is retried
+    fn remove_entry<F>(&self, uri: &str, remove: F) -> RemoteResult<()>
+    where
+        F: Fn(&SmbClient, &str) -> SmbResult<()>,
+    {
+        let err = match remove(&self.client, uri) {
+            Ok(()) => return Ok(()),
+            Err(e) => self.remote_error(e, RemoteErrorType::CouldNotRemoveFile),
+        };
+        if !self.force_readonly || err.kind != RemoteErrorType::PexError {
+            return Err(err);
+        }
+        debug!("clearing read-only attribute of {}", uri);
+        // libsmbclient sets the read-only attribute only if no write bit is set
+        self.client
+            .chmod(uri, SmbMode::from(0o755))
+            .and_then(|_| remove(&self.client, uri))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotRemoveFile))
+    }
+
+    /// Drop the cached metadata and content of `path`
+    fn invalidate(&self, path: &Path) {
+        self.cache.invalidate(path);
+        self.data_cache.invalidate(path);
+    }
+
+    /// Build a `RemoteError` out of `err`, keeping track of connection failures
+    fn remote_error<E: StatusCode>(&self, err: E, fallback: RemoteErrorType) -> RemoteError {
+        self.liveness.observe(error::remote_error(err, fallback))
+    }
+
+    /// Create the file at `uri` and write `reader` into it
+    fn write_file(
+        &self,
+        uri: &str,
+        metadata: &Metadata,
+        reader: &mut (dyn Read + Send),
+    ) -> RemoteResult<u64> {
+        let file = self
+            .client
+            .open_with(
+                uri,
+                SmbOpenOptions::default()
+                    .create(true)
+                    .write(true)
+                    // neither the tail of a longer file nor its data in place of the holes
+                    // must be left behind
+                    .truncate(true)
+                    .mode(
+                        u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t,
+                    ),
+            )
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let mut progress = self.progress(Some(metadata.size).filter(|size| *size > 0));
+        transfer::push(
+            reader,
+            &mut self.throttled(self.reopening(uri, file, 0, self.transfer.sparse)),
+            &self.transfer,
+            &mut progress,
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+        .inspect(|_| self.liveness.seen())
+    }
+
+    /// Read `path` into `dest`, serving it from the data cache if unchanged since it was cached.
+    ///
+    /// The file is stated on the server, bypassing the metadata cache, to compare its size,
+    /// modification and change time, and file ID with those of the cached content.
+    fn open_cached_file(
+        &mut self,
+        path: &Path,
+        mut dest: Box<dyn Write + Send>,
+    ) -> RemoteResult<u64> {
+        let uri = self.get_uri(path);
+        let stat = smb_utils::raw_stat(&self.client, &format!("{}{}", self.share_uri, uri))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))?;
+        let version = smb_utils::content_version(&stat);
+        let total = version.size();
+        let mut progress = self.progress(Some(total));
+        let version = Some(version).filter(|_| {
+            usize::try_from(total).is_ok_and(|size| size <= self.data_cache.max_file_size())
+        });
+        if let Some(data) = version
+            .as_ref()
+            .and_then(|version| self.data_cache.get(Path::new(&uri), version))
+        {
+            trace!("reading file at {} from cache", uri);
+            return transfer::pull(&mut data.as_ref(), &mut dest, &self.transfer, &mut progress)
+                .map_err(|e| self.remote_error(e, RemoteErrorType::IoError));
+        }
+        trace!("opening file at {} for read", uri);
+        let mut file = self
+            .client
+            .open_with(uri.as_str(), SmbOpenOptions::default().read(true))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let Some(version) = version else {
+            return transfer::pull(
+                &mut self.throttled(&mut file),
+                &mut dest,
+                &self.transfer,
+                &mut progress,
+            )
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen());
+        };
+        let mut dest = Tee::new(dest, self.data_cache.max_file_size());
+        let size = transfer::pull(
+            &mut self.throttled(&mut file),
+            &mut dest,
+            &self.transfer,
+            &mut progress,
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        self.liveness.seen();
+        // the file may have changed since `stat`
+        if let Some(data) = dest.into_data().filter(|_| size == total) {
+            self.data_cache
+                .insert(Path::new(&uri), version, Arc::from(data));
+        }
+        Ok(size)
+    }
+
+    /// Wrap `file`, opened at `uri` and positioned at `offset`, so that a new handle is opened
+    /// and the write resumed if the connection is lost (see [`TransferOptions::resume_attempts`]).
+    /// If `sparse`, the zeros are skipped (see [`TransferOptions::sparse`])
+    fn reopening<'a>(
+        &'a self,
+        uri: &'a str,
+        file: SmbFile<'a>,
+        offset: u64,
+        sparse: bool,
+    ) -> Sparse<Reopening<SmbFile<'a>, impl FnMut(u64) -> io::Result<SmbFile<'a>> + 'a>> {
+        let reopen = move |written: u64| {
+            debug!("reopening {} at {}", uri, offset + written);
+            let mut file = self
+                .client
+                .open_with(uri, SmbOpenOptions::default().write(true))
+                .map_err(|err| match err {
+                    SmbError::Io(err) => err,
+                    err => io::Error::other(err),
+                })?;
+            file.seek(SeekFrom::Start(offset + written))?;
+            Ok(file)
+        };
+        // the holes are skipped outside of the reopening writer, so that the trailing one
+        // is still closed by `flush` after the handle has been reopened
+        Sparse::new(
+            Reopening::new(file, reopen, self.transfer.resume_attempts),
+            sparse,
+        )
+    }
+
+    /// Account the reads and writes on `inner` in the client rate limiter
+    fn throttled<T>(&self, inner: T) -> Throttled<T> {
+        Throttled::new(inner, self.limiter.clone())
+    }
+
+    /// Start tracking the progress of a transfer of `total` bytes
+    fn progress(&self, total: Option<u64>) -> Progress {
+        Progress::new(self.on_progress.clone(), self.cancellation.clone(), total)
+    }
+
+    /// Whether `uri` is the root of the server, above the shares, in multi-share mode
+    fn is_server_root(&self, uri: &str) -> bool {
+        self.multi_share && uri == "/"
+    }
+
     fn get_uri<P: AsRef<Path>>(&self, p: P) -> String {
         let p = path_utils::absolutize(self.wrkdir.as_path(), p.as_ref());
         p.to_string_lossy().to_string()
@@ -65,13 +744,22 @@ impl SmbFs {
 
 impl RemoteFs for SmbFs {
     fn connect(&mut self) -> RemoteResult<Welcome> {
-        // Get user to check whether connection works
-        self.check_connection()?;
+        SmbContextOptions::global()
+            .apply(&self.client)
+            .map_err(|e| error::remote_error(e, RemoteErrorType::ConnectionError))?;
+        if let Some(level) = self.encryption {
+            debug!("setting encryption level to {:?}", level);
+            smb_utils::set_encryption_level(&self.client, level)
+                .map_err(|e| error::remote_error(e, RemoteErrorType::ConnectionError))?;
+        }
+        // Probe the share to check whether connection works
+        self.probe()?;
         Ok(Welcome::default())
     }
 
     fn disconnect(&mut self) -> RemoteResult<()> {
-        self.check_connection()
+        self.liveness.lost();
+        Ok(())
     }
 
     fn is_connected(&mut self) -> bool {
@@ -102,36 +790,62 @@ impl RemoteFs for SmbFs {
     }
 
     fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
-        self.check_connection()?;
         let path = self.get_uri(path);
+        if let Some(files) = self.cache.list(Path::new(&path)) {
+            trace!("listing files at {} from cache", path);
+            return Ok(files);
+        }
+        self.check_connection()?;
         trace!("listing files at {}", path);
         let dirents = self
             .client
             .list_dir(path.as_str())
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::StatFailed, e))?;
-        // stat each dirent (NOTE: KEEP ONLY FILES AND DIRECTORIES)
-        Ok(dirents
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())?;
+        // stat each dirent (NOTE: KEEP ONLY FILES, DIRECTORIES AND LINKS, OR DISK SHARES AT THE SERVER ROOT)
+        let shares = self.is_server_root(&path);
+        let files: Vec<File> = dirents
             .into_iter()
             .filter_map(|d| {
-                if d.get_type() == SmbDirentType::File || d.get_type() == SmbDirentType::Dir {
-                    let p = PathBuf::from(format!("{}/{}", path, d.name()));
+                if d.get_type() == SmbDirentType::File
+                    || d.get_type() == SmbDirentType::Dir
+                    || d.get_type() == SmbDirentType::Link
+                    || (shares && d.get_type() == SmbDirentType::FileShare)
+                {
+                    let p = Path::new(&path).join(d.name());
                     Some(self.stat(&p))
                 } else {
                     None
                 }
             })
             .flatten()
-            .collect())
+            .collect();
+        self.cache.insert_listing(Path::new(&path), &files);
+        Ok(files)
     }
 
     fn stat(&mut self, path: &Path) -> RemoteResult<File> {
-        self.check_connection()?;
         let path = self.get_uri(path);
+        if let Some(file) = self.cache.get(Path::new(&path)) {
+            trace!("get stat for {} from cache", path);
+            return Ok(file);
+        }
+        if self.is_server_root(&path) {
+            return Ok(File {
+                path: PathBuf::from(path),
+                metadata: Metadata::default().file_type(FileType::Directory),
+            });
+        }
+        self.check_connection()?;
         trace!("get stat for {}", path);
         self.client
             .stat(path.as_str())
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::StatFailed, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
             .map(|stat| smb_utils::smbstat_to_file(path, stat))
+            .inspect(|file| {
+                self.liveness.seen();
+                self.cache.insert(file);
+            })
     }
 
     fn setstat(&mut self, _path: &Path, _metadata: Metadata) -> RemoteResult<()> {
@@ -140,10 +854,14 @@ impl RemoteFs for SmbFs {
 
     fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
         trace!("checking if {} exists...", path.display());
+        if let Some(exists) = self.cache.exists(Path::new(&self.get_uri(path))) {
+            return Ok(exists);
+        }
         match self.stat(path) {
             Ok(_) => Ok(true),
+            // a component of the path may be a file (ENOTDIR), which is reported as `BadFile`
             Err(RemoteError {
-                kind: RemoteErrorType::StatFailed,
+                kind: RemoteErrorType::NoSuchFileOrDirectory | RemoteErrorType::BadFile,
                 ..
             }) => Ok(false),
             Err(err) => Err(err),
@@ -154,18 +872,37 @@ impl RemoteFs for SmbFs {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("removing file {}", path);
+        self.invalidate(Path::new(&path));
         self.client
             .unlink(path)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotRemoveFile))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("removing directory at {}", path);
+        self.invalidate(Path::new(&path));
         self.client
             .rmdir(path)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotRemoveFile, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotRemoveFile))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        if !self.stat(path)?.is_dir() {
+            return self.remove_file(path);
+        }
+        let path = self.get_uri(path);
+        trace!("removing all at {}", path);
+        self.invalidate(Path::new(&path));
+        let mut failures = RemoveFailures::default();
+        self.remove_tree(&path, &mut failures);
+        failures
+            .into_result(Path::new(&path))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn create_dir(&mut self, path: &Path, mode: UnixPex) -> RemoteResult<()> {
@@ -175,10 +912,12 @@ impl RemoteFs for SmbFs {
         }
         let path = self.get_uri(path);
         trace!("making directory at {}", path);
+        self.invalidate(Path::new(&path));
         // check if directory exists
         self.client
             .mkdir(path, SmbMode::from(u32::from(mode) as mode_t))
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::FileCreateDenied, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::FileCreateDenied))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn symlink(&mut self, _path: &Path, _target: &Path) -> RemoteResult<()> {
@@ -194,10 +933,19 @@ impl RemoteFs for SmbFs {
         let src = self.get_uri(src);
         let dest = self.get_uri(dest);
         trace!("moving {} to {}", src, dest);
+        self.invalidate(Path::new(&src));
+        self.invalidate(Path::new(&dest));
         // check if directory exists
         self.client
             .rename(src, dest)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::ProtocolError, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::ProtocolError))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    #[cfg(feature = "find")]
+    fn find(&mut self, search: &str) -> RemoteResult<Vec<File>> {
+        let wrkdir = self.pwd()?;
+        self.find_iter(&wrkdir, FindOptions::new(search)).collect()
     }
 
     fn exec(&mut self, _cmd: &str) -> RemoteResult<(u32, String)> {
@@ -213,10 +961,11 @@ impl RemoteFs for SmbFs {
         self.check_connection()?;
         let path = self.get_uri(path);
         trace!("opening file at {} for append", path);
+        self.invalidate(Path::new(&path));
         let mut file = self
             .client
             .open_with(
-                path,
+                path.as_str(),
                 SmbOpenOptions::default()
                     .create(true)
                     .append(true)
@@ -225,9 +974,16 @@ impl RemoteFs for SmbFs {
                         u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t,
                     ),
             )
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
-        io::copy(&mut reader, &mut file)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let offset = file
+            .seek(SeekFrom::End(0))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        // holes would be skipped by the writes in append mode
+        let mut writer = self.throttled(self.reopening(&path, file, offset, false));
+        let mut progress = self.progress(Some(metadata.size).filter(|size| *size > 0));
+        transfer::push(&mut reader, &mut writer, &self.transfer, &mut progress)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn create_file(
@@ -238,32 +994,61 @@ impl RemoteFs for SmbFs {
     ) -> RemoteResult<u64> {
         self.check_connection()?;
         let path = self.get_uri(path);
-        trace!("creating file at {}", path);
-        let mut file = self
-            .client
-            .open_with(
-                path,
-                SmbOpenOptions::default()
-                    .create(true)
-                    .write(true)
-                    .mode(
-                        u32::from(metadata.mode.unwrap_or_else(|| UnixPex::from(0o644))) as mode_t,
-                    ),
-            )
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
-        io::copy(&mut reader, &mut file)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+        self.invalidate(Path::new(&path));
+        match self.atomic.as_ref() {
+            None => {
+                trace!("creating file at {}", path);
+                self.write_file(path.as_str(), metadata, reader.as_mut())
+            }
+            Some(naming) => {
+                let temp = naming
+                    .temp_path(Path::new(&path))
+                    .to_string_lossy()
+                    .to_string();
+                trace!("creating file at {} through {}", path, temp);
+                let result = self
+                    .write_file(temp.as_str(), metadata, reader.as_mut())
+                    .and_then(|size| {
+                        self.client
+                            .rename(temp.as_str(), path.as_str())
+                            .map(|_| size)
+                            .map_err(|e| self.remote_error(e, RemoteErrorType::ProtocolError))
+                    });
+                if result.is_err() {
+                    if let Err(err) = self.client.unlink(temp.as_str()) {
+                        warn!("failed to remove temporary file {}: {}", temp, err);
+                    }
+                }
+                result
+            }
+        }
     }
 
     fn open_file(&mut self, path: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
         self.check_connection()?;
+        if self.data_cache.capacity() > 0 {
+            return self.open_cached_file(path, dest);
+        }
+        // the size is needed only to report progress
+        let total = match self.on_progress.is_some() {
+            true => Some(self.stat(path)?.metadata.size),
+            false => None,
+        };
         let path = self.get_uri(path);
         trace!("opening file at {} for read", path);
         let mut file = self
             .client
             .open_with(path, SmbOpenOptions::default().read(true))
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
-        io::copy(&mut file, &mut dest).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let mut progress = self.progress(total);
+        transfer::pull(
+            &mut self.throttled(&mut file),
+            &mut dest,
+            &self.transfer,
+            &mut progress,
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+        .inspect(|_| self.liveness.seen())
     }
 
     fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
@@ -279,6 +1064,87 @@ impl RemoteFs for SmbFs {
     }
 }
 
+#[cfg(feature = "find")]
+impl Walk for SmbFs {
+    fn list(
+        &mut self,
+        dir: &Path,
+        _pattern: Option<&str>,
+        _descend: bool,
+    ) -> RemoteResult<Vec<File>> {
+        // libsmbclient always enumerates directories with the `*` search pattern
+        self.list_dir(dir)
+    }
+
+    fn stat_target(&mut self, path: &Path) -> RemoteResult<File> {
+        self.stat(path)
+    }
+}
+
+/// Iterator over the entries of a directory, returned by [`SmbFs::read_dir`]
+pub struct ReadDir<'a> {
+    dir: String,
+    entries: Entries<'a>,
+}
+
+enum Entries<'a> {
+    /// Directory kept open, read one entry at a time
+    Directory(smb_utils::Directory<'a>),
+    Listing(std::vec::IntoIter<SmbDirentInfo>),
+    /// Shares of the server, already stat'd
+    Files(std::vec::IntoIter<File>),
+}
+
+impl ReadDir<'_> {
+    /// Yield the remaining entries sorted by name, instead of in server order.
+    ///
+    /// This reads all of them at once.
+    pub fn sorted(mut self) -> Self {
+        self.entries = match self.entries {
+            Entries::Directory(entries) => {
+                let mut entries: Vec<SmbDirentInfo> = entries.collect();
+                entries.sort_by(|a, b| a.name.cmp(&b.name));
+                Entries::Listing(entries.into_iter())
+            }
+            Entries::Listing(entries) => {
+                let mut entries: Vec<SmbDirentInfo> = entries.collect();
+                entries.sort_by(|a, b| a.name.cmp(&b.name));
+                Entries::Listing(entries.into_iter())
+            }
+            Entries::Files(files) => {
+                let mut files: Vec<File> = files.collect();
+                files.sort_by_key(|file| file.name());
+                Entries::Files(files.into_iter())
+            }
+        };
+        self
+    }
+}
+
+impl Iterator for ReadDir<'_> {
+    type Item = RemoteResult<File>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.entries {
+            Entries::Directory(entries) => entries
+                .next()
+                .map(|info| Ok(smb_utils::dirent_info_to_file(&self.dir, info))),
+            Entries::Listing(entries) => entries
+                .next()
+                .map(|info| Ok(smb_utils::dirent_info_to_file(&self.dir, info))),
+            Entries::Files(files) => files.next().map(Ok),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.entries {
+            Entries::Directory(entries) => entries.size_hint(),
+            Entries::Listing(entries) => entries.size_hint(),
+            Entries::Files(files) => files.size_hint(),
+        }
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "with-containers")]
 mod test {
@@ -453,6 +1319,44 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_create_file_atomically() {
+        crate::mock::logger();
+        let mut client = init_client().atomic_writes(Some(TempNaming::default()));
+        let p = Path::new("/cargo-test/a.txt");
+        for file_data in ["test data\n", "new data\n"] {
+            let reader = Cursor::new(file_data.as_bytes());
+            assert!(client
+                .create_file(p, &Metadata::default(), Box::new(reader))
+                .is_ok());
+        }
+        assert_eq!(client.stat(p).unwrap().metadata().size, 9);
+        // temporary file must be gone
+        assert_eq!(client.list_dir(Path::new("/cargo-test")).unwrap().len(), 1);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_invalidate_cached_metadata() {
+        crate::mock::logger();
+        let mut client = init_client().cache_metadata(Duration::from_secs(60));
+        let p = Path::new("/cargo-test/a.txt");
+        assert!(!client.exists(p).unwrap());
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(client.list_dir(Path::new("/cargo-test")).unwrap().len(), 1);
+        assert!(client.exists(p).unwrap());
+        assert!(client.remove_file(p).is_ok());
+        assert!(!client.exists(p).unwrap());
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -504,6 +1408,8 @@ mod test {
             false
         );
         assert_eq!(client.exists(Path::new("/cargo-test/")).ok().unwrap(), true);
+        // a component of the path is a file
+        assert!(!client.exists(Path::new("/cargo-test/a.txt/b.txt")).unwrap());
         finalize_client(client);
     }
 
@@ -616,6 +1522,58 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_open_cached_file() {
+        crate::mock::logger();
+        let mut client = init_client().cache_data(1024, 1024);
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        for _ in 0..2 {
+            let buffer: Box<dyn std::io::Write + Send> = Box::new(Vec::new());
+            assert_eq!(client.open_file(p, buffer).unwrap(), 10);
+        }
+        // the content is refreshed once changed
+        let reader = Cursor::new("more test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let buffer: Box<dyn std::io::Write + Send> = Box::new(Vec::new());
+        assert_eq!(client.open_file(p, buffer).unwrap(), 15);
+        // and once rewritten with the same size
+        let reader = Cursor::new("MORE TEST DATA\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let local = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(
+            client
+                .open_file(p, Box::new(local.reopen().unwrap()))
+                .unwrap(),
+            15
+        );
+        assert_eq!(std::fs::read(local.path()).unwrap(), b"MORE TEST DATA\n");
+        // larger files are streamed without being cached
+        let p = Path::new("/cargo-test/b.bin");
+        let reader = Cursor::new(vec![1; 2048]);
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let local = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(
+            client
+                .open_file(p, Box::new(local.reopen().unwrap()))
+                .unwrap(),
+            2048
+        );
+        assert_eq!(std::fs::read(local.path()).unwrap(), vec![1; 2048]);
+        finalize_client(client);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -630,6 +1588,115 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_resume_upload() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test ".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let mut local = tempfile::NamedTempFile::new().unwrap();
+        local.write_all(b"test data\n").unwrap();
+        assert_eq!(client.resume_upload(local.path(), p).unwrap(), 5);
+        assert_eq!(client.stat(p).unwrap().metadata().size, 10);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_report_progress() {
+        crate::mock::logger();
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let mut client = init_client()
+            .transfer_options(TransferOptions::default().chunk_size(64 * 1024))
+            .on_progress(move |progress| {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((progress.transferred, progress.total))
+            });
+        let p = Path::new("/cargo-test/a.bin");
+        let data = vec![1; 3 * 64 * 1024];
+        let metadata = Metadata::default().size(data.len() as u64);
+        assert!(client
+            .create_file(p, &metadata, Box::new(Cursor::new(data.clone())))
+            .is_ok());
+        let total = Some(data.len() as u64);
+        assert_eq!(
+            reports.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![(65536, total), (131072, total), (196608, total)]
+        );
+        let buffer: Box<dyn std::io::Write + Send> = Box::new(Vec::new());
+        assert_eq!(client.open_file(p, buffer).unwrap(), data.len() as u64);
+        assert_eq!(reports.lock().unwrap().last(), Some(&(196608, total)));
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_cancel_transfer() {
+        crate::mock::logger();
+        let client =
+            init_client().transfer_options(TransferOptions::default().chunk_size(64 * 1024));
+        let token = client.cancellation_token();
+        let mut client = client.on_progress(move |_| token.cancel());
+        let p = Path::new("/cargo-test/a.bin");
+        let reader = Cursor::new(vec![1; 3 * 64 * 1024]);
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_err());
+        // the token is reset once the transfer has been aborted
+        assert!(!client.cancellation_token().is_cancelled());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_address_shares_as_directories() {
+        crate::mock::logger();
+        let mut client = SmbFs::try_new(
+            SmbCredentials::default()
+                .server("smb://localhost:3445")
+                .share("")
+                .username("test")
+                .password("test")
+                .workgroup("pavao"),
+            SmbOptions::default()
+                .case_sensitive(true)
+                .one_share_per_server(true),
+        )
+        .unwrap()
+        .multi_share(true);
+        assert!(client.connect().is_ok());
+        // the shares are listed at the root of the server
+        let shares = client.list_dir(Path::new("/")).unwrap();
+        assert!(shares
+            .iter()
+            .any(|share| share.is_dir() && share.name() == "temp"));
+        assert!(client.stat(Path::new("/temp")).unwrap().is_dir());
+        let p = Path::new("/temp/cargo-test/a.txt");
+        assert!(client
+            .create_dir(Path::new("/temp/cargo-test"), UnixPex::from(0o755))
+            .is_ok());
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(client.stat(p).unwrap().metadata().size, 10);
+        // the shares can't be changed
+        assert!(client.remove_dir(Path::new("/temp")).is_err());
+        assert!(client.remove_dir_all(Path::new("/temp/cargo-test")).is_ok());
+        assert!(client.disconnect().is_ok());
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     #[serial]
@@ -640,6 +1707,122 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_remove_dir_tree() {
+        crate::mock::logger();
+        let mut client = init_client().force_remove_readonly(true);
+        let dir = Path::new("/cargo-test/tree");
+        for p in [dir, &dir.join("sub"), &dir.join("sub/deeper")] {
+            assert!(client.create_dir(p, UnixPex::from(0o775)).is_ok());
+            let reader = Cursor::new("test data\n".as_bytes());
+            assert!(client
+                .create_file(&p.join("a.txt"), &Metadata::default(), Box::new(reader))
+                .is_ok());
+        }
+        assert!(client.remove_dir_all(dir).is_ok());
+        assert!(!client.exists(dir).unwrap());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_read_dir_lazily() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let dir = Path::new("/cargo-test/read_dir");
+        assert!(client.create_dir(dir, UnixPex::from(0o775)).is_ok());
+        for name in ["b.txt", "a.txt"] {
+            let reader = Cursor::new("test data\n".as_bytes());
+            assert!(client
+                .create_file(&dir.join(name), &Metadata::default(), Box::new(reader))
+                .is_ok());
+        }
+        let files: Vec<File> = client
+            .read_dir(dir)
+            .unwrap()
+            .sorted()
+            .collect::<RemoteResult<_>>()
+            .unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), dir.join("a.txt").as_path());
+        assert_eq!(files[0].metadata.size, 10);
+        assert!(client.remove_dir_all(dir).is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_stop_reading_dir_early() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let dir = Path::new("/cargo-test/read_dir_early");
+        assert!(client.create_dir(dir, UnixPex::from(0o775)).is_ok());
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let reader = Cursor::new("test data\n".as_bytes());
+            assert!(client
+                .create_file(&dir.join(name), &Metadata::default(), Box::new(reader))
+                .is_ok());
+        }
+        let mut entries = client.read_dir(dir).unwrap();
+        assert_eq!(entries.size_hint().1, None);
+        let first = entries.next().unwrap().unwrap();
+        assert!(first.is_file());
+        // the directory is closed on drop and the client can be used again
+        drop(entries);
+        assert_eq!(client.read_dir(dir).unwrap().count(), 3);
+        assert!(client.remove_dir_all(dir).is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(all(feature = "with-containers", feature = "find"))]
+    #[serial]
+    fn should_find_files() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let dir = Path::new("/cargo-test/find");
+        for p in [dir, &dir.join("sub"), &dir.join("sub/deeper")] {
+            assert!(client.create_dir(p, UnixPex::from(0o775)).is_ok());
+            for name in ["a.txt", "b.log"] {
+                let reader = Cursor::new("test data\n".as_bytes());
+                assert!(client
+                    .create_file(&p.join(name), &Metadata::default(), Box::new(reader))
+                    .is_ok());
+            }
+        }
+        let find = |client: &mut SmbFs, options: FindOptions| {
+            let mut paths: Vec<PathBuf> = client
+                .find_iter(dir, options)
+                .map(|file| file.unwrap().path().to_path_buf())
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            find(&mut client, FindOptions::new("*.txt")),
+            vec![
+                dir.join("a.txt"),
+                dir.join("sub/a.txt"),
+                dir.join("sub/deeper/a.txt")
+            ]
+        );
+        assert_eq!(
+            find(&mut client, FindOptions::new("*").max_depth(2).min_size(1)),
+            vec![
+                dir.join("a.txt"),
+                dir.join("b.log"),
+                dir.join("sub/a.txt"),
+                dir.join("sub/b.log")
+            ]
+        );
+        assert!(client.remove_dir_all(dir).is_ok());
+        finalize_client(client);
+    }
+
     /*
     #[test]
     #[cfg(feature = "with-containers")]
@@ -812,6 +1995,254 @@ mod test {
         finalize_client(client);
     }
 
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_require_encryption() {
+        crate::mock::logger();
+        let mut client = init_client().encryption_level(SmbEncryptionLevel::Request);
+        assert!(client.connect().is_ok());
+        assert_eq!(client.is_encrypted(), None);
+        let mut client = init_client().encryption_level(SmbEncryptionLevel::Require);
+        assert!(client.connect().is_ok());
+        // libsmbclient doesn't report the state of the session
+        assert_eq!(client.is_encrypted(), None);
+        // the level is set on the context shared with the other tests
+        assert!(smb_utils::set_encryption_level(client.client(), SmbEncryptionLevel::None).is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[cfg(smbc_configuration)]
+    #[serial]
+    fn should_require_signing() {
+        crate::mock::logger();
+        assert!(SmbContextOptions::default()
+            .signing(Some(SmbSigning::Required))
+            .set_global()
+            .is_ok());
+        let mut client = init_client();
+        assert!(client.connect().is_ok());
+        assert!(client.list_dir(Path::new("/")).is_ok());
+        // libsmbclient doesn't report the state of the session
+        assert_eq!(client.is_signed(), None);
+        // the options are global to the process, hence shared with the other tests
+        assert!(SmbContextOptions::default()
+            .signing(Some(SmbSigning::Default))
+            .set_global()
+            .is_ok());
+        assert!(client.connect().is_ok());
+        assert!(SmbContextOptions::default().set_global().is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[cfg(smbc_posix_extensions)]
+    #[serial]
+    fn should_request_posix_extensions() {
+        crate::mock::logger();
+        assert!(!SmbContextOptions::global().has_posix_extensions());
+        assert!(SmbContextOptions::default()
+            .posix_extensions(true)
+            .set_global()
+            .is_ok());
+        let mut client = init_client();
+        assert!(client.connect().is_ok());
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let file = client.stat(p).unwrap();
+        assert!(file.is_file());
+        assert!(file.metadata().mode.is_some());
+        // the options are global to the process, hence shared with the other tests
+        assert!(SmbContextOptions::default().set_global().is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[cfg(smbc_protocols)]
+    #[serial]
+    fn should_restrict_dialects() {
+        crate::mock::logger();
+        assert!(SmbContextOptions::default()
+            .dialects(Some(SmbDialect::Smb311), None)
+            .set_global()
+            .is_ok());
+        let mut client = init_client();
+        assert!(client.connect().is_ok());
+        assert!(client.list_dir(Path::new("/cargo-test")).is_ok());
+        // libsmbclient doesn't report the negotiated dialect
+        assert_eq!(client.dialect(), None);
+        // the options are global to the process, hence shared with the other tests: restore the defaults
+        assert!(SmbContextOptions::default()
+            .dialects(Some(SmbDialect::Smb202), Some(SmbDialect::Smb311))
+            .set_global()
+            .is_ok());
+        assert!(client.connect().is_ok());
+        assert!(SmbContextOptions::default().set_global().is_ok());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_create_file_ending_with_hole() {
+        crate::mock::logger();
+        let mut client = init_client().transfer_options(
+            TransferOptions::default()
+                .chunk_size(64 * 1024)
+                .sparse(true)
+                .resume_attempts(1),
+        );
+        let p = Path::new("/cargo-test/sparse.bin");
+        let mut data = b"test data\n".to_vec();
+        data.resize(256 * 1024, 0);
+        let reader = Cursor::new(data.clone());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        // the trailing hole is closed on flush, through the reopening writer
+        assert_eq!(client.stat(p).unwrap().metadata().size, data.len() as u64);
+        let local = tempfile::NamedTempFile::new().unwrap();
+        assert!(client
+            .open_file(p, Box::new(local.reopen().unwrap()))
+            .is_ok());
+        assert!(std::fs::read(local.path()).unwrap() == data);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_get_file_info() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        let info = client.file_info(p).unwrap();
+        assert_eq!(info.links, Some(1));
+        assert!(info.file_id.is_some());
+        assert!(info.volume_serial.is_some());
+        assert_eq!(info.file_id_128, None);
+        // the ID assigned by the server is kept across renames
+        let dest = Path::new("/cargo-test/b.txt");
+        assert!(client.mov(p, dest).is_ok());
+        assert_eq!(client.file_info(dest).unwrap(), info);
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_not_get_dfs_targets() {
+        crate::mock::logger();
+        let mut client = init_client();
+        assert_eq!(
+            client
+                .dfs_targets(Path::new("/cargo-test"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_not_manage_sparse_files() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(
+            client.set_sparse(p, true).unwrap_err().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        assert_eq!(
+            client.punch_hole(p, 0, 4).unwrap_err().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        assert_eq!(
+            client.allocated_ranges(p).unwrap_err().kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_truncate_overwritten_file() {
+        crate::mock::logger();
+        for sparse in [false, true] {
+            let mut client =
+                init_client().transfer_options(TransferOptions::default().sparse(sparse));
+            let p = Path::new("/cargo-test/a.txt");
+            let reader = Cursor::new("more test data\n".as_bytes());
+            assert!(client
+                .create_file(p, &Metadata::default(), Box::new(reader))
+                .is_ok());
+            let reader = Cursor::new("test data\n".as_bytes());
+            assert!(client
+                .create_file(p, &Metadata::default(), Box::new(reader))
+                .is_ok());
+            assert_eq!(client.stat(p).unwrap().metadata().size, 10);
+            finalize_client(client);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_not_make_hard_link() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(
+            client
+                .hard_link(p, Path::new("/cargo-test/b.txt"))
+                .unwrap_err()
+                .kind,
+            RemoteErrorType::UnsupportedFeature
+        );
+        assert!(!client.exists(Path::new("/cargo-test/b.txt")).unwrap());
+        finalize_client(client);
+    }
+
+    #[test]
+    #[cfg(feature = "with-containers")]
+    #[serial]
+    fn should_not_read_link_of_file() {
+        crate::mock::logger();
+        let mut client = init_client();
+        let p = Path::new("/cargo-test/a.txt");
+        let reader = Cursor::new("test data\n".as_bytes());
+        assert!(client
+            .create_file(p, &Metadata::default(), Box::new(reader))
+            .is_ok());
+        assert_eq!(
+            client.read_link(p).unwrap_err().kind,
+            RemoteErrorType::BadFile
+        );
+        finalize_client(client);
+    }
+
     fn init_client() -> SmbFs {
         let _ = std::fs::remove_dir_all(Path::new("/tmp/cargo-test"));
         let client = SmbFs::try_new(
@@ -0,0 +1,270 @@
+//! # Context
+//!
+//! Options of the libsmbclient context shared by all the clients of the process
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use pavao::SmbClient;
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+
+use super::SmbSigning;
+use crate::dialect::SmbDialect;
+#[cfg(any(smbc_posix_extensions, smbc_configuration, smbc_protocols))]
+use crate::utils::smb as smb_utils;
+
+/// Options of the process-wide libsmbclient context
+static GLOBAL: Mutex<SmbContextOptions> = Mutex::new(SmbContextOptions {
+    posix_extensions: false,
+    signing: None,
+    configuration: None,
+    min_dialect: None,
+    max_dialect: None,
+});
+
+/// Options of the libsmbclient context.
+///
+/// pavao keeps a single libsmbclient context for the whole process, which all the [`super::SmbFs`]
+/// share, and frees it as soon as any of them is dropped. These options are thus process-global:
+/// they are installed with [`SmbContextOptions::set_global`], then set on the context by every
+/// `SmbFs::connect`, so that they survive the context being created anew.
+///
+/// libsmbclient reuses the connections it already has to a server, so the options only apply to the
+/// connections established after they are set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmbContextOptions {
+    posix_extensions: bool,
+    signing: Option<SmbSigning>,
+    configuration: Option<PathBuf>,
+    min_dialect: Option<SmbDialect>,
+    max_dialect: Option<SmbDialect>,
+}
+
+impl SmbContextOptions {
+    /// Request the SMB3.1.1 POSIX extensions when connecting to a server.
+    ///
+    /// When the server (Samba) grants them, `stat` reports the actual mode, uid and gid of the entries
+    /// and symlinks, paths are case sensitive, and renames and unlinks follow POSIX semantics.
+    /// Otherwise the session silently falls back to the plain SMB semantics; libsmbclient doesn't
+    /// tell whether the server granted them.
+    ///
+    /// It requires libsmbclient 4.21 or later, the build providing `smbc_setOptionPosixExtensions`.
+    pub fn posix_extensions(mut self, enable: bool) -> Self {
+        self.posix_extensions = enable;
+        self
+    }
+
+    /// Whether the POSIX extensions are requested
+    pub fn has_posix_extensions(&self) -> bool {
+        self.posix_extensions
+    }
+
+    /// Set the signing policy of the connections, as the `client signing` parameter of `smb.conf`.
+    ///
+    /// libsmbclient has no option for it, so the parameter is loaded with `smbc_setConfiguration` from a
+    /// file written to the temporary directory. libsmbclient keeps its parameters for the whole process
+    /// and not per context: `None` leaves the policy as it was last loaded, `SmbSigning::Default` restores
+    /// the default one.
+    pub fn signing(mut self, policy: Option<SmbSigning>) -> Self {
+        self.signing = policy;
+        self
+    }
+
+    /// Get the signing policy set on the connections, if any
+    pub fn signing_policy(&self) -> Option<SmbSigning> {
+        self.signing
+    }
+
+    /// Load the parameters of libsmbclient from the `smb.conf` file at `path`, e.g. the signing
+    /// algorithms (`client smb3 signing algorithms`) or the ciphers (`client smb3 encryption algorithms`).
+    ///
+    /// The parameters missing from the file are left unchanged; the signing policy set with
+    /// [`SmbContextOptions::signing`] is loaded afterwards, so it overrides the one of the file.
+    pub fn configuration(mut self, path: Option<&Path>) -> Self {
+        self.configuration = path.map(Path::to_path_buf);
+        self
+    }
+
+    /// Restrict the dialects negotiated with the servers to the range between `min` and `max`.
+    ///
+    /// `connect` fails with `ProtocolError` if the server supports none of them, unless libsmbclient
+    /// reuses a connection it already has to the server, negotiated before the range was set.
+    /// A `None` bound is left as it was last set, libsmbclient's default being `SMB2_02` to `SMB3_11`.
+    /// libsmbclient keeps the negotiated dialect to itself, so it can't be reported.
+    ///
+    /// It requires libsmbclient 4.8 or later, the build providing `smbc_setOptionProtocols`.
+    pub fn dialects(mut self, min: Option<SmbDialect>, max: Option<SmbDialect>) -> Self {
+        self.min_dialect = min;
+        self.max_dialect = max;
+        self
+    }
+
+    /// Get the range of dialects set with [`SmbContextOptions::dialects`]
+    pub fn dialect_range(&self) -> (Option<SmbDialect>, Option<SmbDialect>) {
+        (self.min_dialect, self.max_dialect)
+    }
+
+    /// Install these options for all the clients of the process, from their next `connect` on.
+    ///
+    /// Fails with `UnsupportedFeature` if the libsmbclient the crate was built against can't set
+    /// one of them; the global options are then left unchanged.
+    pub fn set_global(self) -> RemoteResult<()> {
+        if self.posix_extensions && !cfg!(smbc_posix_extensions) {
+            return Err(unsupported("smbc_setOptionPosixExtensions"));
+        }
+        if (self.signing.is_some() || self.configuration.is_some()) && !cfg!(smbc_configuration) {
+            return Err(unsupported("smbc_setConfiguration"));
+        }
+        if (self.min_dialect.is_some() || self.max_dialect.is_some()) && !cfg!(smbc_protocols) {
+            return Err(unsupported("smbc_setOptionProtocols"));
+        }
+        *GLOBAL.lock().unwrap() = self;
+        Ok(())
+    }
+
+    /// Get the options installed for all the clients of the process
+    pub fn global() -> Self {
+        GLOBAL.lock().unwrap().clone()
+    }
+
+    /// Set the options on the libsmbclient context of `client`
+    #[cfg_attr(
+        not(any(smbc_posix_extensions, smbc_configuration, smbc_protocols)),
+        allow(unused_variables)
+    )]
+    pub(crate) fn apply(&self, client: &SmbClient) -> io::Result<()> {
+        #[cfg(smbc_posix_extensions)]
+        smb_utils::set_posix_extensions(client, self.posix_extensions)?;
+        #[cfg(smbc_configuration)]
+        if let Some(path) = self.configuration.as_deref() {
+            smb_utils::set_configuration(client, path)?;
+        }
+        #[cfg(smbc_configuration)]
+        if let Some(policy) = self.signing {
+            load_signing(client, policy)?;
+        }
+        #[cfg(smbc_protocols)]
+        if self.min_dialect.is_some() || self.max_dialect.is_some() {
+            debug!(
+                "restricting dialects to {:?}..{:?}",
+                self.min_dialect, self.max_dialect
+            );
+            smb_utils::set_protocols(client, self.min_dialect, self.max_dialect)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make the error returned when the libsmbclient the crate was built against lacks `function`
+fn unsupported(function: &str) -> RemoteError {
+    RemoteError::new_ex(
+        RemoteErrorType::UnsupportedFeature,
+        format!("libsmbclient doesn't provide {function}"),
+    )
+}
+
+/// Load the `client signing` parameter for `policy`, through a temporary `smb.conf` file
+#[cfg(smbc_configuration)]
+fn load_signing(client: &SmbClient, policy: SmbSigning) -> io::Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        ".remotefs-smb-{}-{}.conf",
+        std::process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, signing_configuration(policy))?;
+    let result = smb_utils::set_configuration(client, &path);
+    if let Err(err) = std::fs::remove_file(&path) {
+        warn!("could not remove {}: {}", path.display(), err);
+    }
+    result
+}
+
+/// Make the `smb.conf` content setting the signing `policy`
+#[cfg(smbc_configuration)]
+fn signing_configuration(policy: SmbSigning) -> String {
+    format!("[global]\n\tclient signing = {}\n", policy.samba_name())
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn should_set_global_options() {
+        assert_eq!(SmbContextOptions::global(), SmbContextOptions::default());
+        let options = SmbContextOptions::default().posix_extensions(true);
+        assert!(options.has_posix_extensions());
+        #[cfg(smbc_posix_extensions)]
+        {
+            assert!(options.clone().set_global().is_ok());
+            assert_eq!(SmbContextOptions::global(), options);
+        }
+        #[cfg(not(smbc_posix_extensions))]
+        {
+            let err = options.set_global().unwrap_err();
+            assert_eq!(err.kind, RemoteErrorType::UnsupportedFeature);
+            assert_eq!(SmbContextOptions::global(), SmbContextOptions::default());
+        }
+        assert!(SmbContextOptions::default().set_global().is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn should_set_global_signing() {
+        let options = SmbContextOptions::default()
+            .signing(Some(SmbSigning::Required))
+            .configuration(Some(Path::new("/etc/samba/smb.conf")));
+        assert_eq!(options.signing_policy(), Some(SmbSigning::Required));
+        #[cfg(smbc_configuration)]
+        {
+            assert!(options.clone().set_global().is_ok());
+            assert_eq!(SmbContextOptions::global(), options);
+        }
+        #[cfg(not(smbc_configuration))]
+        {
+            let err = options.set_global().unwrap_err();
+            assert_eq!(err.kind, RemoteErrorType::UnsupportedFeature);
+        }
+        assert!(SmbContextOptions::default().set_global().is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn should_set_global_dialects() {
+        let options = SmbContextOptions::default()
+            .dialects(Some(SmbDialect::Smb300), Some(SmbDialect::Smb311));
+        assert_eq!(
+            options.dialect_range(),
+            (Some(SmbDialect::Smb300), Some(SmbDialect::Smb311))
+        );
+        #[cfg(smbc_protocols)]
+        {
+            assert!(options.clone().set_global().is_ok());
+            assert_eq!(SmbContextOptions::global(), options);
+        }
+        #[cfg(not(smbc_protocols))]
+        {
+            let err = options.set_global().unwrap_err();
+            assert_eq!(err.kind, RemoteErrorType::UnsupportedFeature);
+        }
+        assert!(SmbContextOptions::default().set_global().is_ok());
+    }
+
+    #[test]
+    #[cfg(smbc_configuration)]
+    fn should_make_signing_configuration() {
+        assert_eq!(
+            signing_configuration(SmbSigning::Required),
+            "[global]\n\tclient signing = required\n"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+/// Credentials used to connect to the SMB server.
+///
+/// Unlike `pavao::SmbCredentials`, the server and the share are kept, to build the URI of the
+/// requests made directly on the libsmbclient context
+#[derive(Debug, Default, Clone)]
+pub struct SmbCredentials {
+    pub(crate) server: String,
+    pub(crate) share: String,
+    pub(crate) username: String,
+    pub(crate) password: String,
+    pub(crate) workgroup: String,
+}
+
+impl SmbCredentials {
+    /// Construct SmbCredentials with the provided password
+    pub fn password<S: AsRef<str>>(mut self, password: S) -> Self {
+        self.password = password.as_ref().to_string();
+        self
+    }
+
+    /// Construct SmbCredentials with the provided server
+    pub fn server<S: AsRef<str>>(mut self, server: S) -> Self {
+        self.server = server.as_ref().to_string();
+        self
+    }
+
+    /// Construct SmbCredentials with the provided share
+    pub fn share<S: AsRef<str>>(mut self, share: S) -> Self {
+        self.share = share.as_ref().to_string();
+        self
+    }
+
+    /// Construct SmbCredentials with the provided username
+    pub fn username<S: AsRef<str>>(mut self, username: S) -> Self {
+        self.username = username.as_ref().to_string();
+        self
+    }
+
+    /// Construct SmbCredentials with the provided workgroup
+    pub fn workgroup<S: AsRef<str>>(mut self, workgroup: S) -> Self {
+        self.workgroup = workgroup.as_ref().to_string();
+        self
+    }
+
+    /// Get the URI of the share, built as pavao does
+    pub(crate) fn share_uri(&self) -> String {
+        let separator = match self.share.starts_with('/') {
+            true => "",
+            false => "/",
+        };
+        format!("{}{}{}", self.server, separator, self.share)
+    }
+}
+
+impl From<SmbCredentials> for pavao::SmbCredentials {
+    fn from(credentials: SmbCredentials) -> Self {
+        pavao::SmbCredentials::default()
+            .server(credentials.server)
+            .share(credentials.share)
+            .username(credentials.username)
+            .password(credentials.password)
+            .workgroup(credentials.workgroup)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_construct_credentials() {
+        let credentials = SmbCredentials::default()
+            .server("smb://localhost:3445")
+            .share("/temp")
+            .username("test")
+            .password("foobar")
+            .workgroup("pavao");
+        assert_eq!(&credentials.server, "smb://localhost:3445");
+        assert_eq!(&credentials.share, "/temp");
+        assert_eq!(&credentials.username, "test");
+        assert_eq!(&credentials.password, "foobar");
+        assert_eq!(&credentials.workgroup, "pavao");
+    }
+
+    #[test]
+    fn should_get_share_uri() {
+        let credentials = SmbCredentials::default()
+            .server("smb://localhost:3445")
+            .share("/temp");
+        assert_eq!(credentials.share_uri(), "smb://localhost:3445/temp");
+        assert_eq!(
+            credentials.share("temp").share_uri(),
+            "smb://localhost:3445/temp"
+        );
+        assert_eq!(
+            SmbCredentials::default()
+                .server("smb://localhost")
+                .share_uri(),
+            "smb://localhost/"
+        );
+    }
+}
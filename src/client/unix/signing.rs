@@ -0,0 +1,44 @@
+/// Signing policy of the connections established by libsmbclient, as its `client signing` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmbSigning {
+    /// Sign when the server requires it, or always for SMB3 and later
+    #[default]
+    Default,
+    /// Don't offer signing for SMB1; SMB2 and later still sign when the server requires it
+    Disabled,
+    /// Sign whenever the server supports it
+    Desired,
+    /// Fail to connect if the server doesn't sign the session, and reject the unsigned responses
+    Required,
+}
+
+impl SmbSigning {
+    /// Get the value of the `client signing` parameter of `smb.conf` for this policy
+    #[cfg(smbc_configuration)]
+    pub(crate) fn samba_name(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Disabled => "disabled",
+            Self::Desired => "desired",
+            Self::Required => "required",
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(smbc_configuration)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_get_samba_name() {
+        assert_eq!(SmbSigning::default(), SmbSigning::Default);
+        assert_eq!(SmbSigning::Default.samba_name(), "default");
+        assert_eq!(SmbSigning::Disabled.samba_name(), "disabled");
+        assert_eq!(SmbSigning::Desired.samba_name(), "desired");
+        assert_eq!(SmbSigning::Required.samba_name(), "required");
+    }
+}
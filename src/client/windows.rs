@@ -3,19 +3,82 @@
 //! Windows implementation of Smb fs client
 
 mod credentials;
+mod encryption;
 mod file_stream;
 
-use std::ffi::CString;
+#[cfg(feature = "find")]
+use std::collections::HashSet;
+use std::ffi::{c_void, CString, OsString};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 pub use credentials::SmbCredentials;
+pub use encryption::SmbEncryptionLevel;
 use file_stream::FileStream;
 use filetime::{self, FileTime};
 use remotefs::fs::stream::{ReadAndSeek, WriteAndSeek};
-use remotefs::fs::{File, Metadata, ReadStream, UnixPex, Welcome, WriteStream};
+use remotefs::fs::{File, FileType, Metadata, ReadStream, UnixPex, Welcome, WriteStream};
 use remotefs::{RemoteError, RemoteErrorType, RemoteFs, RemoteResult};
-use windows_sys::Win32::Foundation::{NO_ERROR, TRUE};
+#[cfg(feature = "find")]
+use windows_sys::Win32::Foundation::{
+    ERROR_FILE_NOT_FOUND, ERROR_NO_MORE_FILES, FILETIME, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Foundation::{
+    ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, HANDLE, NO_ERROR, TRUE,
+};
+use windows_sys::Win32::NetworkManagement::NetManagement::NetApiBufferFree;
 use windows_sys::Win32::NetworkManagement::WNet;
+use windows_sys::Win32::Storage::DistributedFileSystem::{
+    NetDfsGetClientInfo, DFS_INFO_3, DFS_STORAGE_INFO, DFS_STORAGE_STATE_ACTIVE,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    FileBasicInfo, FileIdInfo, FileRemoteProtocolInfo, GetFileInformationByHandle,
+    GetFileInformationByHandleEx, BY_HANDLE_FILE_INFORMATION, DELETE, FILE_BASIC_INFO,
+    FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_DELETE_ON_CLOSE, FILE_FLAG_OPEN_REPARSE_POINT,
+    FILE_ID_INFO, FILE_READ_ATTRIBUTES, FILE_REMOTE_PROTOCOL_INFO, FILE_SHARE_DELETE,
+    FILE_SHARE_READ, FILE_SHARE_WRITE,
+};
+#[cfg(feature = "find")]
+use windows_sys::Win32::Storage::FileSystem::{
+    FindClose, FindExInfoBasic, FindExSearchLimitToDirectories, FindExSearchNameMatch,
+    FindFirstFileExW, FindNextFileW, FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_REPARSE_POINT,
+    FIND_FIRST_EX_LARGE_FETCH, WIN32_FIND_DATAW,
+};
+use windows_sys::Win32::System::Ioctl::{
+    FILE_ALLOCATED_RANGE_BUFFER, FILE_SET_SPARSE_BUFFER, FILE_ZERO_DATA_INFORMATION,
+    FSCTL_QUERY_ALLOCATED_RANGES, FSCTL_SET_SPARSE, FSCTL_SET_ZERO_DATA,
+};
+use windows_sys::Win32::System::IO::DeviceIoControl;
+
+use crate::atomic::TempNaming;
+use crate::cache::{ContentVersion, DataCache, MetadataCache, Tee};
+use crate::dfs::SmbDfsTarget;
+use crate::dialect::SmbDialect;
+use crate::error::{self, RemoveFailures, StatusCode};
+#[cfg(feature = "find")]
+use crate::find::{Find, FindOptions, Walk};
+use crate::info::SmbFileInfo;
+use crate::liveness::Liveness;
+use crate::transfer::{
+    self, CancellationToken, Progress, ProgressCallback, RateLimiter, Throttled, TransferOptions,
+    TransferProgress,
+};
+
+/// Flag of `FILE_REMOTE_PROTOCOL_INFO` set when the handles to the share are persistent (ntifs.h)
+const REMOTE_PROTOCOL_INFO_FLAG_PERSISTENT_HANDLE: u32 = 0x0000_0004;
+/// Flag of `FILE_REMOTE_PROTOCOL_INFO` set when the connection is encrypted (ntifs.h)
+const REMOTE_PROTOCOL_INFO_FLAG_PRIVACY: u32 = 0x0000_0008;
+/// Flag of `FILE_REMOTE_PROTOCOL_INFO` set when the connection is signed (ntifs.h)
+const REMOTE_PROTOCOL_INFO_FLAG_INTEGRITY: u32 = 0x0000_0010;
+/// Capability of the server set when it supports multichannel (MS-SMB2 2.2.4)
+const SMB2_GLOBAL_CAP_MULTI_CHANNEL: u32 = 0x0000_0008;
 
 /// SMB file system client
 pub struct SmbFs {
@@ -24,6 +87,17 @@ pub struct SmbFs {
     credentials: SmbCredentials,
     wrkdir: PathBuf,
     is_connected: bool,
+    transfer: TransferOptions,
+    atomic: Option<TempNaming>,
+    on_progress: Option<ProgressCallback>,
+    cancellation: CancellationToken,
+    limiter: RateLimiter,
+    cache: MetadataCache,
+    data_cache: DataCache,
+    liveness: Liveness,
+    force_readonly: bool,
+    multi_share: bool,
+    encryption: SmbEncryptionLevel,
 }
 
 impl SmbFs {
@@ -36,7 +110,475 @@ impl SmbFs {
             credentials,
             wrkdir: PathBuf::from("\\"),
             is_connected: false,
+            transfer: TransferOptions::default(),
+            atomic: None,
+            on_progress: None,
+            cancellation: CancellationToken::default(),
+            limiter: RateLimiter::default(),
+            cache: MetadataCache::default(),
+            data_cache: DataCache::default(),
+            liveness: Liveness::default(),
+            force_readonly: false,
+            multi_share: false,
+            encryption: SmbEncryptionLevel::None,
+        }
+    }
+
+    /// Set the encryption level requested by `connect`.
+    ///
+    /// With `SmbEncryptionLevel::Require`, the connection is added with `CONNECT_REQUIRE_PRIVACY`, so
+    /// `connect` fails if the server can't encrypt it. Otherwise the redirector negotiates encryption
+    /// on its own, according to the share settings and the client configuration
+    /// (e.g. `Set-SmbClientConfiguration -RequireEncryption $true`). The redirector shares the
+    /// connection to a server among all the clients, and doesn't report the cipher.
+    pub fn encryption_level(mut self, level: SmbEncryptionLevel) -> Self {
+        self.encryption = level;
+        self
+    }
+
+    /// Whether the connection to the share of the working directory is encrypted, if known.
+    ///
+    /// The state is read from the actual connection, through its FileRemoteProtocolInfo; it is `None`
+    /// when not connected, at the root of the server with `multi_share`, or when the redirector doesn't
+    /// report it (before Windows 8). The cipher isn't reported.
+    pub fn is_encrypted(&self) -> Option<bool> {
+        self.remote_protocol_flag(REMOTE_PROTOCOL_INFO_FLAG_PRIVACY)
+    }
+
+    /// Whether the connection to the share of the working directory is signed, if known.
+    ///
+    /// As [`SmbFs::is_encrypted`], the state is read from the actual connection. The signing policy can't
+    /// be set per connection: it is configured on the SMB redirector for the whole machine
+    /// (e.g. `Set-SmbClientConfiguration -RequireSecuritySignature $true`).
+    pub fn is_signed(&self) -> Option<bool> {
+        self.remote_protocol_flag(REMOTE_PROTOCOL_INFO_FLAG_INTEGRITY)
+    }
+
+    /// Whether the handles to the share of the working directory are persistent, if known.
+    ///
+    /// The redirector requests durable handles, and persistent ones on continuously available shares,
+    /// and reclaims them by itself after a network failure; this reports whether the share grants
+    /// persistent handles, read from its FileRemoteProtocolInfo.
+    pub fn has_persistent_handles(&self) -> Option<bool> {
+        self.remote_protocol_flag(REMOTE_PROTOCOL_INFO_FLAG_PERSISTENT_HANDLE)
+    }
+
+    /// Whether the server of the share of the working directory supports SMB3 multichannel, if known.
+    ///
+    /// Multichannel is negotiated by the redirector: it queries the interfaces of the server, binds the
+    /// additional channels to the session and spreads the requests across them, which `SmbFs` can't
+    /// control. This reports the capability advertised by the server, read from its FileRemoteProtocolInfo;
+    /// the channels in use aren't reported (see `Get-SmbMultichannelConnection`).
+    pub fn supports_multichannel(&self) -> Option<bool> {
+        self.remote_protocol()
+            .and_then(|info| server_capability(&info, SMB2_GLOBAL_CAP_MULTI_CHANNEL))
+    }
+
+    /// Get the dialect negotiated with the server of the share of the working directory, if known.
+    ///
+    /// The dialect is read from the actual connection, through its FileRemoteProtocolInfo. The range of
+    /// dialects can't be restricted per connection: it is configured on the SMB redirector for the whole
+    /// machine (e.g. `Set-SmbClientConfiguration -Smb2DialectMin SMB311` on Windows 11 24H2 and later).
+    pub fn dialect(&self) -> Option<SmbDialect> {
+        self.remote_protocol().and_then(|info| {
+            SmbDialect::from_protocol_version(
+                info.ProtocolMajorVersion,
+                info.ProtocolMinorVersion,
+                info.ProtocolRevision,
+            )
+        })
+    }
+
+    /// Get whether `flag` is set in the remote protocol information of the share of the working directory
+    fn remote_protocol_flag(&self, flag: u32) -> Option<bool> {
+        self.remote_protocol()
+            .and_then(|info| protocol_flag(&info, flag))
+    }
+
+    /// Get the remote protocol information of the share of the working directory, if connected to it
+    fn remote_protocol(&self) -> Option<FILE_REMOTE_PROTOCOL_INFO> {
+        let path = self.full_path(&self.wrkdir);
+        if !self.is_connected || self.is_server_root(&path) {
+            return None;
+        }
+        match remote_protocol_info(&path) {
+            Ok(info) => Some(info),
+            Err(err) => {
+                debug!(
+                    "could not get the remote protocol information of {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Make `create_file` atomic: the file is written to a temporary file in the same directory,
+    /// named after `naming`, which is then renamed over the target, replacing it if it exists.
+    /// On failure the temporary file is removed and the target is left untouched.
+    pub fn atomic_writes(mut self, naming: Option<TempNaming>) -> Self {
+        self.atomic = naming;
+        self
+    }
+
+    /// Set the options used by `open_file`, `create_file` and `append_file`.
+    ///
+    /// Files are split into ranges, each one read or written by a worker with its own handle,
+    /// so that multiple READ/WRITE requests are outstanding at the same time.
+    pub fn transfer_options(mut self, options: TransferOptions) -> Self {
+        self.transfer = options;
+        self
+    }
+
+    /// Set a callback to be notified about the progress of `open_file`, `create_file`, `append_file`
+    /// and resumed transfers
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&TransferProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Get the token to cancel the running transfer
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// Limit the bandwidth used by this client to `rate` bytes per second, with bursts up to `burst` bytes
+    pub fn rate_limit(self, rate: u64, burst: u64) -> Self {
+        self.limiter.set_limit(Some(rate), burst);
+        self
+    }
+
+    /// Get the rate limiter shared by all the transfers and streams of this client,
+    /// which can be used to change the limit at runtime
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.limiter.clone()
+    }
+
+    /// Cache the metadata returned by `stat` and `list_dir` for `ttl`.
+    ///
+    /// While cached, `stat`, `exists` and `list_dir` don't contact the server at all.
+    /// The entries are invalidated by the mutations performed through this client.
+    pub fn cache_metadata(self, ttl: Duration) -> Self {
+        self.cache.set_ttl(Some(ttl));
+        self
+    }
+
+    /// Get the metadata cache of this client, which can be used to invalidate entries
+    /// changed by other clients (e.g. on change notifications)
+    pub fn metadata_cache(&self) -> MetadataCache {
+        self.cache.clone()
+    }
+
+    /// Cache the content of the files read with `open_file`, up to `capacity` bytes in total;
+    /// files larger than `max_file_size` are streamed without being cached.
+    ///
+    /// The redirector already caches the data of the files while it holds a lease on them, but
+    /// each `open_file` still opens the file on the server. **This cache is not lease-backed**:
+    /// a cached content is served after checking that the size, modification and change time and
+    /// file ID of the file haven't changed, which the redirector answers from its own cache.
+    ///
+    /// A file rewritten by another client with the same size within the granularity of the
+    /// timestamps of the server file system (e.g. 2 seconds on FAT) is served stale.
+    pub fn cache_data(self, capacity: usize, max_file_size: usize) -> Self {
+        self.data_cache.set_limits(capacity, max_file_size);
+        self
+    }
+
+    /// Set the interval after which an idle connection is probed before being used (60 seconds by default).
+    ///
+    /// The connection is seen alive whenever an operation succeeds, and lost when one fails with a
+    /// connection error. The first operation after it has been lost, or idle for longer than `interval`,
+    /// is preceded by a probe (a query of the root of the share), so that it fails early with `ConnectionError`.
+    /// Nothing keeps the connection alive while idle. `None` disables the probes of idle connections.
+    pub fn idle_probe_interval(mut self, interval: Option<Duration>) -> Self {
+        self.liveness.set_idle_probe(interval);
+        self
+    }
+
+    /// Search `dir` recursively for the entries matching `options`, reporting them as they are found
+    #[cfg(feature = "find")]
+    pub fn find_iter(&mut self, dir: &Path, options: FindOptions) -> Find<'_> {
+        Find::new(self, dir, options)
+    }
+
+    /// Iterate over the entries of the directory at `path`, in the order they are returned by the server.
+    ///
+    /// The entries are fetched page by page with QUERY_DIRECTORY requests as the iterator advances,
+    /// and their metadata comes from the listing itself, without stat'ing them one by one.
+    pub fn read_dir(&mut self, path: &Path) -> RemoteResult<ReadDir<'_>> {
+        self.check_connection()?;
+        let abs_path = self.full_path(path);
+        debug!("reading dir {}", abs_path.display());
+        if self.is_server_root(&abs_path) {
+            let shares = self.list_dir(path)?;
+            return Ok(ReadDir {
+                entries: Entries::Files(shares.into_iter()),
+                fs: PhantomData,
+            });
         }
+        std::fs::read_dir(&abs_path)
+            .map(|entries| ReadDir {
+                entries: Entries::Lazy(Box::new(entries)),
+                fs: PhantomData,
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Address all the shares of the server as the top level directories of the paths (`\\share\path`),
+    /// instead of the share of the credentials, which is then ignored. Listing `\\` returns the disk shares.
+    ///
+    /// `connect` authenticates a single session on the `IPC$` share of the server,
+    /// which is then used to access all its shares.
+    pub fn multi_share(mut self, enable: bool) -> Self {
+        self.multi_share = enable;
+        let server = format!("\\\\{}", self.credentials.server);
+        (self.remote_name, self.remote_path) = match enable {
+            true => (format!("{server}\\IPC$"), PathBuf::from(server)),
+            false => {
+                let remote_name = format!("{server}\\{}", self.credentials.share);
+                (remote_name.clone(), PathBuf::from(remote_name))
+            }
+        };
+        self
+    }
+
+    /// Get the target of the symlink at `path`, read from its IO_REPARSE_TAG_SYMLINK reparse point
+    pub fn read_link(&mut self, path: &Path) -> RemoteResult<PathBuf> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!("reading link {}", path.display());
+        std::fs::read_link(path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Get the metadata of the entry at `path` (absolute), not following symlinks
+    fn lstat(&self, path: &Path) -> RemoteResult<File> {
+        std::fs::symlink_metadata(path)
+            .map(|metadata| to_file(path.to_path_buf(), metadata))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Create a hard link at `new` to the file at `existing`, through a FileLinkInformation SET_INFO request
+    pub fn hard_link(&mut self, existing: &Path, new: &Path) -> RemoteResult<()> {
+        self.check_connection()?;
+        let existing = self.full_path(existing);
+        let new = self.full_path(new);
+        debug!(
+            "creating hard link {} -> {}",
+            new.display(),
+            existing.display()
+        );
+        // the link count of the existing file changes too
+        self.invalidate(&existing);
+        self.invalidate(&new);
+        std::fs::hard_link(&existing, &new)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::FileCreateDenied))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Get the targets of the DFS link `path` crosses, as cached by the DFS client of the redirector
+    /// (NetDfsGetClientInfo).
+    ///
+    /// The redirector resolves the referrals by itself, caches them for their TTL and fails over between
+    /// their targets; the paths crossing a DFS link can be used as any other path.
+    pub fn dfs_targets(&mut self, path: &Path) -> RemoteResult<Vec<SmbDfsTarget>> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!("getting DFS targets of {}", path.display());
+        dfs_client_info(&path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Mark the file at `path` as sparse, or as not sparse, through FSCTL_SET_SPARSE
+    pub fn set_sparse(&mut self, path: &Path, sparse: bool) -> RemoteResult<()> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!("setting sparse flag of {} to {}", path.display(), sparse);
+        self.invalidate(&path);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| set_sparse(&file, sparse))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Zero `len` bytes of the file at `path` from `offset` through FSCTL_SET_ZERO_DATA.
+    ///
+    /// The zeroed range is deallocated only if the file is sparse (see [`SmbFs::set_sparse`])
+    pub fn punch_hole(&mut self, path: &Path, offset: u64, len: u64) -> RemoteResult<()> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!(
+            "punching hole in {} at {} ({} bytes)",
+            path.display(),
+            offset,
+            len
+        );
+        self.invalidate(&path);
+        let zero_data = FILE_ZERO_DATA_INFORMATION {
+            FileOffset: offset as i64,
+            BeyondFinalZero: offset.saturating_add(len) as i64,
+        };
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|file| fsctl(&file, FSCTL_SET_ZERO_DATA, &zero_data))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Get the ranges of the file at `path` which are allocated on disk, through
+    /// FSCTL_QUERY_ALLOCATED_RANGES.
+    ///
+    /// A file which is not sparse is reported as allocated as a whole
+    pub fn allocated_ranges(&mut self, path: &Path) -> RemoteResult<Vec<Range<u64>>> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!("querying allocated ranges of {}", path.display());
+        std::fs::File::open(&path)
+            .and_then(|file| query_allocated_ranges(&file))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Get the SMB specific metadata of the entry at `path`, not following symlinks
+    pub fn file_info(&mut self, path: &Path) -> RemoteResult<SmbFileInfo> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        debug!("get file info for {}", path.display());
+        file_information(&path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+
+    /// Make `remove_dir_all` clear the read-only attribute of the entries it can't remove because of it
+    pub fn force_remove_readonly(mut self, force: bool) -> Self {
+        self.force_readonly = force;
+        self
+    }
+
+    /// Upload `local` to `remote`, resuming from the size of `remote` if it already exists.
+    ///
+    /// If `remote` is larger than `local` or its tail doesn't match (see [`TransferOptions::verify_tail`]),
+    /// the upload starts over. Returns the amount of bytes written by this call.
+    pub fn resume_upload(&mut self, local: &Path, remote: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let remote = self.full_path(remote);
+        self.invalidate(&remote);
+        let mut source = std::fs::File::open(local)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let mut partial = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&remote)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let offset = Self::resume_offset(&mut source, &mut partial, self.transfer.verify_tail)?;
+        debug!(
+            "uploading {} to {} from {}",
+            local.display(),
+            remote.display(),
+            offset
+        );
+        let total = partial
+            .set_len(offset)
+            .and_then(|_| source.seek(SeekFrom::Start(offset)))
+            .and_then(|_| source.metadata())
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?
+            .len()
+            - offset;
+        transfer::upload_ranges(
+            || {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&remote)
+                    .map(|file| self.throttled(file))
+            },
+            offset,
+            &mut source,
+            &self.transfer,
+            &mut self.progress(Some(total)),
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+        .inspect(|_| self.liveness.seen())
+    }
+
+    /// Download `remote` to `local`, resuming from the size of `local` if it already exists.
+    ///
+    /// If `local` is larger than `remote` or its tail doesn't match (see [`TransferOptions::verify_tail`]),
+    /// the download starts over. Returns the amount of bytes written by this call.
+    pub fn resume_download(&mut self, remote: &Path, local: &Path) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let remote = self.full_path(remote);
+        let mut source = std::fs::File::open(&remote)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let mut partial = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(local)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        let offset = Self::resume_offset(&mut source, &mut partial, self.transfer.verify_tail)?;
+        debug!(
+            "downloading {} to {} from {}",
+            remote.display(),
+            local.display(),
+            offset
+        );
+        let size = source
+            .metadata()
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?
+            .len();
+        partial
+            .set_len(offset)
+            .and_then(|_| partial.seek(SeekFrom::Start(offset)))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        transfer::download_ranges(
+            || std::fs::File::open(&remote).map(|file| self.throttled(file)),
+            offset,
+            size,
+            &mut partial,
+            &self.transfer,
+            &mut self.progress(Some(size - offset)),
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+        .inspect(|_| self.liveness.seen())
+    }
+
+    /// Get the offset to resume the transfer from `source` into `partial`
+    fn resume_offset(
+        source: &mut std::fs::File,
+        partial: &mut std::fs::File,
+        verify_tail: usize,
+    ) -> RemoteResult<u64> {
+        let source_size = source.metadata().map(|m| m.len());
+        let partial_size = partial.metadata().map(|m| m.len());
+        source_size
+            .and_then(|source_size| {
+                transfer::resume_offset(source, source_size, partial, partial_size?, verify_tail)
+            })
+            .map_err(|e| error::remote_error(e, RemoteErrorType::IoError))
+    }
+
+    /// Account the reads and writes on `inner` in the client rate limiter
+    fn throttled<T>(&self, inner: T) -> Throttled<T> {
+        Throttled::new(inner, self.limiter.clone())
+    }
+
+    /// Start tracking the progress of a transfer of `total` bytes
+    fn progress(&self, total: Option<u64>) -> Progress {
+        Progress::new(self.on_progress.clone(), self.cancellation.clone(), total)
     }
 
     /// Get full path for entry
@@ -49,12 +591,154 @@ impl SmbFs {
         full_path
     }
 
+    /// Create the file at `path` and write `reader` into it
+    fn write_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        reader: &mut (dyn Read + Send),
+    ) -> RemoteResult<u64> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        if self.transfer.sparse {
+            // the zeros are skipped anyway, but take space on disk
+            if let Err(err) = set_sparse(&file, true) {
+                warn!("failed to mark {} as sparse: {}", path.display(), err);
+            }
+        }
+        drop(file);
+        let size = transfer::upload_ranges(
+            || {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .map(|file| self.throttled(file))
+            },
+            0,
+            reader,
+            &self.transfer,
+            &mut self.progress(Some(metadata.size).filter(|size| *size > 0)),
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+
+        self.setstat(path, metadata.clone())?;
+
+        Ok(size)
+    }
+
+    /// Make sure the connection can be used, probing it only if it has been lost or idle for too long
     fn check_connection(&mut self) -> RemoteResult<()> {
-        if self.is_connected() {
-            Ok(())
-        } else {
-            Err(RemoteError::new(RemoteErrorType::NotConnected))
+        if !self.is_connected {
+            return Err(RemoteError::new(RemoteErrorType::NotConnected));
         }
+        if self.liveness.needs_probe() {
+            trace!("checking connection...");
+            // in multi-share mode, the root of the server can only be listed
+            let result = match self.multi_share {
+                true => list_shares(&self.remote_path).map(drop),
+                false => std::fs::metadata(&self.remote_path).map(drop),
+            };
+            result.map_err(|e| {
+                error!("connection ERROR: {}", e);
+                let mut err = error::remote_error(e, RemoteErrorType::ConnectionError);
+                if err.kind != RemoteErrorType::AuthenticationFailed {
+                    err.kind = RemoteErrorType::ConnectionError;
+                }
+                self.liveness.observe(err)
+            })?;
+            self.liveness.seen();
+        }
+        Ok(())
+    }
+
+    /// Whether `path` is the root of the server, above the shares, in multi-share mode
+    fn is_server_root(&self, path: &Path) -> bool {
+        self.multi_share
+            && Path::new(path.as_os_str().to_string_lossy().trim_end_matches('\\'))
+                == self.remote_path
+    }
+
+    /// Remove the directory at `path` with all its content, enumerating each directory once.
+    /// Entries which can't be removed are recorded in `failures`
+    fn remove_tree(&self, path: &Path, failures: &mut RemoveFailures) {
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                failures.push(path, self.remote_error(e, RemoteErrorType::StatFailed));
+                return;
+            }
+        };
+        let failed = failures.len();
+        for entry in entries {
+            let result = entry.and_then(|entry| Ok((entry.path(), entry.file_type()?)));
+            let (child, file_type) = match result {
+                Ok(entry) => entry,
+                Err(e) => {
+                    failures.push(path, self.remote_error(e, RemoteErrorType::StatFailed));
+                    continue;
+                }
+            };
+            // links are removed, not followed
+            if file_type.is_dir() {
+                self.remove_tree(&child, failures);
+            } else if let Err(err) = self.remove_entry(&child) {
+                failures.push(child, err);
+            }
+        }
+        // the directory can't be empty if any of its entries is left
+        if failures.len() == failed {
+            if let Err(err) = self.remove_entry(path) {
+                failures.push(path, err);
+            }
+        }
+    }
+
+    /// Remove the entry at `path`.
+    /// If forced, the read-only attribute is cleared when the removal is denied, then it is retried
+    #[allow(clippy::permissions_set_readonly_false)] // only clears FILE_ATTRIBUTE_READONLY on Windows
+    fn remove_entry(&self, path: &Path) -> RemoteResult<()> {
+        let err = match Self::delete_on_close(path) {
+            Ok(()) => return Ok(()),
+            Err(e) => self.remote_error(e, RemoteErrorType::CouldNotRemoveFile),
+        };
+        if !self.force_readonly || err.kind != RemoteErrorType::PexError {
+            return Err(err);
+        }
+        debug!("clearing read-only attribute of {}", path.display());
+        std::fs::symlink_metadata(path)
+            .and_then(|metadata| {
+                let mut permissions = metadata.permissions();
+                permissions.set_readonly(false);
+                std::fs::set_permissions(path, permissions)
+            })
+            .and_then(|_| Self::delete_on_close(path))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotRemoveFile))
+    }
+
+    /// Open `path` with `FILE_FLAG_DELETE_ON_CLOSE`, so that it is deleted as soon as the handle is closed,
+    /// without any further request
+    fn delete_on_close(path: &Path) -> std::io::Result<()> {
+        std::fs::OpenOptions::new()
+            .access_mode(DELETE)
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .custom_flags(
+                FILE_FLAG_DELETE_ON_CLOSE
+                    | FILE_FLAG_BACKUP_SEMANTICS
+                    | FILE_FLAG_OPEN_REPARSE_POINT,
+            )
+            .open(path)
+            .map(drop)
+    }
+
+    /// Drop the cached metadata and content of `path`
+    fn invalidate(&self, path: &Path) {
+        self.cache.invalidate(path);
+        self.data_cache.invalidate(path);
+    }
+
+    /// Build a `RemoteError` out of `err`, keeping track of connection failures
+    fn remote_error<E: StatusCode>(&self, err: E, fallback: RemoteErrorType) -> RemoteError {
+        self.liveness.observe(error::remote_error(err, fallback))
     }
 
     fn to_cstr(s: &str) -> CString {
@@ -72,7 +756,11 @@ impl RemoteFs for SmbFs {
         let mut resource = WNet::NETRESOURCEA {
             dwDisplayType: WNet::RESOURCEDISPLAYTYPE_SHAREADMIN,
             dwScope: WNet::RESOURCE_GLOBALNET,
-            dwType: WNet::RESOURCETYPE_DISK,
+            // IPC$ is not a disk share
+            dwType: match self.multi_share {
+                true => WNet::RESOURCETYPE_ANY,
+                false => WNet::RESOURCETYPE_DISK,
+            },
             dwUsage: WNet::RESOURCEUSAGE_ALL,
             lpComment: std::ptr::null_mut(),
             lpLocalName: std::ptr::null_mut(),
@@ -92,6 +780,14 @@ impl RemoteFs for SmbFs {
             .as_mut()
             .map(|password| Self::to_cstr(password));
 
+        // the redirector fails the connection if it can't be encrypted
+        let flags = match self.encryption {
+            SmbEncryptionLevel::Require => {
+                WNet::CONNECT_INTERACTIVE | WNet::CONNECT_REQUIRE_PRIVACY
+            }
+            SmbEncryptionLevel::None | SmbEncryptionLevel::Request => WNet::CONNECT_INTERACTIVE,
+        };
+
         let result = unsafe {
             let username_ptr = username
                 .as_ref()
@@ -105,24 +801,27 @@ impl RemoteFs for SmbFs {
                 &mut resource as *mut WNet::NETRESOURCEA,
                 password_ptr as *const u8,
                 username_ptr as *const u8,
-                WNet::CONNECT_INTERACTIVE,
+                flags,
             )
         };
 
         if result == NO_ERROR {
             self.is_connected = true;
+            self.liveness.seen();
             debug!("connected to {}", self.remote_path.display());
             Ok(Welcome::default())
         } else {
-            Err(RemoteError::new_ex(
+            Err(error::remote_error(
+                std::io::Error::from_raw_os_error(result as i32),
                 RemoteErrorType::ConnectionError,
-                result,
             ))
         }
     }
 
     fn disconnect(&mut self) -> RemoteResult<()> {
-        self.check_connection()?;
+        if !self.is_connected {
+            return Err(RemoteError::new(RemoteErrorType::NotConnected));
+        }
 
         let remote_name = Self::to_cstr(&self.remote_name);
 
@@ -131,18 +830,19 @@ impl RemoteFs for SmbFs {
 
         if result == NO_ERROR {
             self.is_connected = false;
+            self.liveness.lost();
             debug!("disconnected from {}", self.remote_path.display());
             Ok(())
         } else {
-            Err(RemoteError::new_ex(
+            Err(error::remote_error(
+                std::io::Error::from_raw_os_error(result as i32),
                 RemoteErrorType::ConnectionError,
-                result,
             ))
         }
     }
 
     fn is_connected(&mut self) -> bool {
-        self.is_connected
+        self.check_connection().is_ok()
     }
 
     fn pwd(&mut self) -> RemoteResult<PathBuf> {
@@ -170,55 +870,93 @@ impl RemoteFs for SmbFs {
     fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
         self.check_connection()?;
         let abs_path = self.full_path(path);
+        if let Some(files) = self.cache.list(&abs_path) {
+            debug!("listing dir {} from cache", abs_path.display());
+            return Ok(files);
+        }
+        if self.is_server_root(&abs_path) {
+            debug!("listing shares of {}", self.remote_path.display());
+            let shares = list_shares(&self.remote_path)
+                .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))
+                .inspect(|_| self.liveness.seen())?;
+            let mut fs_entries: Vec<File> = Vec::new();
+            for share in shares {
+                let path = self.remote_path.join(share);
+                match self.stat(&path) {
+                    Ok(entry) => fs_entries.push(entry),
+                    Err(e) => error!("Failed to stat {}: {}", path.display(), e),
+                }
+            }
+            self.cache.insert_listing(&abs_path, &fs_entries);
+            return Ok(fs_entries);
+        }
         debug!("listing dir {}", abs_path.display());
-        match std::fs::read_dir(abs_path) {
+        match std::fs::read_dir(&abs_path) {
             Ok(e) => {
+                self.liveness.seen();
                 let mut fs_entries: Vec<File> = Vec::new();
                 for entry in e.flatten() {
-                    match self.stat(entry.path().as_path()) {
+                    match self.lstat(entry.path().as_path()) {
                         Ok(entry) => fs_entries.push(entry),
                         Err(e) => error!("Failed to stat {}: {}", entry.path().display(), e),
                     }
                 }
+                self.cache.insert_listing(&abs_path, &fs_entries);
                 Ok(fs_entries)
             }
-            Err(err) => Err(RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, err)),
+            Err(err) => Err(self.remote_error(err, RemoteErrorType::CouldNotOpenFile)),
         }
     }
 
     fn stat(&mut self, path: &Path) -> RemoteResult<File> {
         self.check_connection()?;
         let path = self.full_path(path);
+        if let Some(file) = self.cache.get(&path) {
+            debug!("stat {} from cache", path.display());
+            return Ok(file);
+        }
         debug!("stat {}", path.display());
+        if self.is_server_root(&path) {
+            return Ok(File {
+                path,
+                metadata: Metadata::default().file_type(FileType::Directory),
+            });
+        }
 
         let attr = match std::fs::metadata(path.as_path()) {
             Ok(metadata) => metadata,
             Err(err) => {
                 error!("Could not read file metadata: {}", err);
-                return Err(RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, err));
+                return Err(self.remote_error(err, RemoteErrorType::StatFailed));
             }
         };
-        let metadata = Metadata::from(attr);
-        // Match dir / file
-        Ok(File { path, metadata })
+        self.liveness.seen();
+        let file = File {
+            path,
+            metadata: Metadata::from(attr),
+        };
+        self.cache.insert(&file);
+        Ok(file)
     }
 
     fn setstat(&mut self, path: &Path, metadata: Metadata) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("setstat for {}", path.display());
+        self.invalidate(&path);
 
         if let Some(mtime) = metadata.modified {
             let mtime = FileTime::from_system_time(mtime);
             debug!("setting mtime {:?}", mtime);
             filetime::set_file_mtime(&path, mtime)
-                .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
+                .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
         }
         if let Some(atime) = metadata.accessed {
             let atime = FileTime::from_system_time(atime);
             filetime::set_file_atime(path, atime)
-                .map_err(|e| RemoteError::new_ex(RemoteErrorType::CouldNotOpenFile, e))?;
+                .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
         }
+        self.liveness.seen();
         Ok(())
     }
 
@@ -226,43 +964,87 @@ impl RemoteFs for SmbFs {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("checking whether {} exists", path.display());
-        Ok(path.exists())
+        if let Some(exists) = self.cache.exists(&path) {
+            return Ok(exists);
+        }
+        path.try_exists()
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("removing file {}", path.display());
-        std::fs::remove_file(path).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+        self.invalidate(&path);
+        std::fs::remove_file(path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("removing dir {}", path.display());
-        std::fs::remove_dir(path).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+        self.invalidate(&path);
+        std::fs::remove_dir(path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
     }
 
     fn remove_dir_all(&mut self, path: &Path) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("removing all at {}", path.display());
-        std::fs::remove_dir_all(path).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+        self.invalidate(&path);
+        let is_dir = std::fs::symlink_metadata(&path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))?
+            .is_dir();
+        if !is_dir {
+            return self.remove_entry(&path);
+        }
+        let mut failures = RemoveFailures::default();
+        self.remove_tree(&path, &mut failures);
+        failures.into_result(&path)
     }
 
     fn create_dir(&mut self, path: &Path, _mode: UnixPex) -> RemoteResult<()> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("creating dir at {}", path.display());
+        self.invalidate(&path);
         if path.exists() {
             return Err(RemoteError::new(RemoteErrorType::DirectoryAlreadyExists));
         }
-        std::fs::create_dir(&path).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+        std::fs::create_dir(&path)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
     }
 
-    fn symlink(&mut self, _path: &Path, _target: &Path) -> RemoteResult<()> {
+    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
         self.check_connection()?;
-        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+        let path = self.full_path(path);
+        // relative targets are resolved by the client against the directory of the link
+        let target = match target.has_root() {
+            true => self.full_path(target),
+            false => target.to_path_buf(),
+        };
+        debug!(
+            "creating symlink {} -> {}",
+            path.display(),
+            target.display()
+        );
+        self.invalidate(&path);
+        let target_is_dir = path
+            .parent()
+            .map(|parent| parent.join(&target))
+            .is_some_and(|target| target.is_dir());
+        match target_is_dir {
+            true => std::os::windows::fs::symlink_dir(&target, &path),
+            false => std::os::windows::fs::symlink_file(&target, &path),
+        }
+        .map_err(|e| self.remote_error(e, RemoteErrorType::FileCreateDenied))
+        .inspect(|_| self.liveness.seen())
     }
 
     fn copy(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
@@ -270,6 +1052,7 @@ impl RemoteFs for SmbFs {
         let src = self.full_path(src);
         let dest = self.full_path(dest);
         debug!("copying {} to {}", src.display(), dest.display());
+        self.invalidate(&dest);
 
         if src.is_dir() {
             // If destination path doesn't exist, create destination
@@ -301,10 +1084,11 @@ impl RemoteFs for SmbFs {
             // Copy entry path to dest path
             if let Err(err) = std::fs::copy(src, dest.as_path()) {
                 error!("Failed to copy file: {}", err);
-                return Err(RemoteError::new_ex(RemoteErrorType::IoError, err));
+                return Err(self.remote_error(err, RemoteErrorType::IoError));
             }
             debug!("file copied");
         }
+        self.liveness.seen();
         Ok(())
     }
 
@@ -313,8 +1097,18 @@ impl RemoteFs for SmbFs {
         let src = self.full_path(src);
         let dest = self.full_path(dest);
         debug!("moving {} to {}", src.display(), dest.display());
+        self.invalidate(&src);
+        self.invalidate(&dest);
+
+        std::fs::rename(src, dest)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+    }
 
-        std::fs::rename(src, dest).map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+    #[cfg(feature = "find")]
+    fn find(&mut self, search: &str) -> RemoteResult<Vec<File>> {
+        let wrkdir = self.pwd()?;
+        self.find_iter(&wrkdir, FindOptions::new(search)).collect()
     }
 
     fn exec(&mut self, _cmd: &str) -> RemoteResult<(u32, String)> {
@@ -325,13 +1119,16 @@ impl RemoteFs for SmbFs {
         self.check_connection()?;
         let path_abs = self.full_path(path);
         debug!("creating {} for reading...", path_abs.display());
+        self.invalidate(&path_abs);
 
         let writer = std::fs::OpenOptions::new()
             .append(true)
             .open(&path_abs)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
             .map(|file| {
-                WriteStream::from(Box::new(FileStream::from(file)) as Box<dyn WriteAndSeek>)
+                WriteStream::from(
+                    Box::new(self.throttled(FileStream::from(file))) as Box<dyn WriteAndSeek>
+                )
             })?;
 
         self.setstat(path, metadata.clone())?;
@@ -343,11 +1140,14 @@ impl RemoteFs for SmbFs {
         self.check_connection()?;
         let path_abs = self.full_path(path);
         debug!("creating {} for reading...", path_abs.display());
+        self.invalidate(&path_abs);
 
         let writer = std::fs::File::create(path_abs)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
             .map(|file| {
-                WriteStream::from(Box::new(FileStream::from(file)) as Box<dyn WriteAndSeek>)
+                WriteStream::from(
+                    Box::new(self.throttled(FileStream::from(file))) as Box<dyn WriteAndSeek>
+                )
             })?;
 
         self.setstat(path, metadata.clone())?;
@@ -355,23 +1155,885 @@ impl RemoteFs for SmbFs {
         Ok(writer)
     }
 
+    fn append_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        mut reader: Box<dyn Read + Send>,
+    ) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let path_abs = self.full_path(path);
+        debug!("appending to {}...", path_abs.display());
+        self.invalidate(&path_abs);
+
+        let base = std::fs::metadata(&path_abs)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?
+            .len();
+        let size = transfer::upload_ranges(
+            || {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path_abs)
+                    .map(|file| self.throttled(file))
+            },
+            base,
+            &mut reader,
+            &self.transfer,
+            &mut self.progress(Some(metadata.size).filter(|size| *size > 0)),
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+
+        self.setstat(path, metadata.clone())?;
+
+        Ok(size)
+    }
+
+    fn create_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        mut reader: Box<dyn Read + Send>,
+    ) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let path_abs = self.full_path(path);
+        self.invalidate(&path_abs);
+        let temp = match self.atomic.as_ref() {
+            None => {
+                debug!("creating {}...", path_abs.display());
+                return self.write_file(&path_abs, metadata, reader.as_mut());
+            }
+            Some(naming) => naming.temp_path(&path_abs),
+        };
+        debug!(
+            "creating {} through {}...",
+            path_abs.display(),
+            temp.display()
+        );
+        let result = self
+            .write_file(&temp, metadata, reader.as_mut())
+            .and_then(|size| {
+                std::fs::rename(&temp, &path_abs)
+                    .map(|_| size)
+                    .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            });
+        if result.is_err() {
+            if let Err(err) = std::fs::remove_file(&temp) {
+                warn!(
+                    "failed to remove temporary file {}: {}",
+                    temp.display(),
+                    err
+                );
+            }
+        }
+        result
+    }
+
+    fn open_file(&mut self, src: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
+        self.check_connection()?;
+        let path = self.full_path(src);
+        debug!("opening file {} for reading...", path.display());
+
+        let version =
+            content_version(&path).map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        let size = version.size();
+        let mut progress = self.progress(Some(size));
+        if self.data_cache.capacity() == 0
+            || usize::try_from(size).map_or(true, |size| size > self.data_cache.max_file_size())
+        {
+            return transfer::download_ranges(
+                || std::fs::File::open(&path).map(|file| self.throttled(file)),
+                0,
+                size,
+                &mut dest,
+                &self.transfer,
+                &mut progress,
+            )
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen());
+        }
+        if let Some(data) = self.data_cache.get(&path, &version) {
+            trace!("reading file at {} from cache", path.display());
+            return progress
+                .advance(size)
+                .and_then(|_| dest.write_all(&data))
+                .and_then(|_| dest.flush())
+                .map(|_| size)
+                .map_err(|e| self.remote_error(e, RemoteErrorType::IoError));
+        }
+        let mut dest = Tee::new(dest, self.data_cache.max_file_size());
+        let read = transfer::download_ranges(
+            || std::fs::File::open(&path).map(|file| self.throttled(file)),
+            0,
+            size,
+            &mut dest,
+            &self.transfer,
+            &mut progress,
+        )
+        .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))?;
+        self.liveness.seen();
+        // the file may have changed since its metadata was read
+        if let Some(data) = dest.into_data().filter(|data| data.len() as u64 == size) {
+            self.data_cache.insert(&path, version, Arc::from(data));
+        }
+        Ok(read)
+    }
+
     fn open(&mut self, path: &Path) -> RemoteResult<ReadStream> {
         self.check_connection()?;
         let path = self.full_path(path);
         debug!("opening file {} for reading...", path.display());
 
         std::fs::File::open(path)
-            .map_err(|e| RemoteError::new_ex(RemoteErrorType::IoError, e))
-            .map(|file| ReadStream::from(Box::new(FileStream::from(file)) as Box<dyn ReadAndSeek>))
+            .map_err(|e| self.remote_error(e, RemoteErrorType::IoError))
+            .inspect(|_| self.liveness.seen())
+            .map(|file| {
+                ReadStream::from(
+                    Box::new(self.throttled(FileStream::from(file))) as Box<dyn ReadAndSeek>
+                )
+            })
+    }
+}
+
+#[cfg(feature = "find")]
+impl Walk for SmbFs {
+    fn list(
+        &mut self,
+        dir: &Path,
+        pattern: Option<&str>,
+        descend: bool,
+    ) -> RemoteResult<Vec<File>> {
+        self.check_connection()?;
+        let abs_path = self.full_path(dir);
+        if self.is_server_root(&abs_path) {
+            return self.list_dir(dir);
+        }
+        let pattern = pattern.unwrap_or("*");
+        debug!("listing dir {} matching {}", abs_path.display(), pattern);
+        let mut files = find_files(&abs_path, pattern, false)
+            .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+        if descend && pattern != "*" {
+            // the directories must be searched whatever their name
+            let dirs = find_files(&abs_path, "*", true)
+                .map_err(|e| self.remote_error(e, RemoteErrorType::CouldNotOpenFile))?;
+            let found: HashSet<PathBuf> = files.iter().map(|file| file.path.clone()).collect();
+            files.extend(dirs.into_iter().filter(|dir| !found.contains(&dir.path)));
+        }
+        self.liveness.seen();
+        Ok(files)
+    }
+
+    fn stat_target(&mut self, path: &Path) -> RemoteResult<File> {
+        self.check_connection()?;
+        let path = self.full_path(path);
+        std::fs::metadata(&path)
+            .map(|metadata| File {
+                path,
+                metadata: Metadata::from(metadata),
+            })
+            .map_err(|e| self.remote_error(e, RemoteErrorType::StatFailed))
+            .inspect(|_| self.liveness.seen())
+    }
+}
+
+/// Query the information of the entry at `path` through a handle, without following symlinks.
+///
+/// The 128-bit file ID and the 64-bit volume serial are only reported by the servers supporting
+/// FileIdInformation
+fn file_information(path: &Path) -> std::io::Result<SmbFileInfo> {
+    let file = std::fs::OpenOptions::new()
+        .access_mode(FILE_READ_ATTRIBUTES)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(path)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut file_info = SmbFileInfo {
+        links: Some(u64::from(info.nNumberOfLinks)),
+        file_id: Some(u64::from(info.nFileIndexHigh) << 32 | u64::from(info.nFileIndexLow)),
+        file_id_128: None,
+        volume_serial: Some(u64::from(info.dwVolumeSerialNumber)),
+    };
+    let mut id_info: FILE_ID_INFO = unsafe { std::mem::zeroed() };
+    match unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle(),
+            FileIdInfo,
+            &mut id_info as *mut FILE_ID_INFO as *mut c_void,
+            std::mem::size_of::<FILE_ID_INFO>() as u32,
+        )
+    } {
+        0 => debug!(
+            "FileIdInformation is not available for {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ),
+        _ => {
+            file_info.file_id_128 = Some(u128::from_le_bytes(id_info.FileId.Identifier));
+            file_info.volume_serial = Some(id_info.VolumeSerialNumber);
+        }
+    }
+    Ok(file_info)
+}
+
+/// Get the version of the content of the file at `path`, to validate the data cache; an unset
+/// last write time compares as the UNIX epoch
+fn content_version(path: &Path) -> std::io::Result<ContentVersion> {
+    let file = std::fs::OpenOptions::new()
+        .access_mode(FILE_READ_ATTRIBUTES)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .open(path)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut basic_info: FILE_BASIC_INFO = unsafe { std::mem::zeroed() };
+    if unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle(),
+            FileBasicInfo,
+            &mut basic_info as *mut FILE_BASIC_INFO as *mut c_void,
+            std::mem::size_of::<FILE_BASIC_INFO>() as u32,
+        )
+    } == 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    let size = u64::from(info.nFileSizeHigh) << 32 | u64::from(info.nFileSizeLow);
+    let modified =
+        intervals_to_system_time(basic_info.LastWriteTime as u64).unwrap_or(SystemTime::UNIX_EPOCH);
+    Ok(ContentVersion::new(size, modified)
+        .changed(intervals_to_system_time(basic_info.ChangeTime as u64))
+        .file_id(Some(
+            u64::from(info.nFileIndexHigh) << 32 | u64::from(info.nFileIndexLow),
+        )))
+}
+
+/// Query the information about the protocol of the connection to the entry at `path`
+fn remote_protocol_info(path: &Path) -> std::io::Result<FILE_REMOTE_PROTOCOL_INFO> {
+    let file = std::fs::OpenOptions::new()
+        .access_mode(FILE_READ_ATTRIBUTES)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)?;
+    let mut info: FILE_REMOTE_PROTOCOL_INFO = unsafe { std::mem::zeroed() };
+    match unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle(),
+            FileRemoteProtocolInfo,
+            &mut info as *mut FILE_REMOTE_PROTOCOL_INFO as *mut c_void,
+            std::mem::size_of::<FILE_REMOTE_PROTOCOL_INFO>() as u32,
+        )
+    } {
+        0 => Err(std::io::Error::last_os_error()),
+        _ => Ok(info),
+    }
+}
+
+/// Get whether `flag` is set in `info`; the flags about the security of the connection are only
+/// reported from the version 2 of the structure on (Windows 8)
+fn protocol_flag(info: &FILE_REMOTE_PROTOCOL_INFO, flag: u32) -> Option<bool> {
+    (info.StructureVersion >= 2).then_some(info.Flags & flag != 0)
+}
+
+/// Get whether the server reported `capability` in `info`; the capabilities are only reported from
+/// the version 2 of the structure on (Windows 8)
+fn server_capability(info: &FILE_REMOTE_PROTOCOL_INFO, capability: u32) -> Option<bool> {
+    (info.StructureVersion >= 2)
+        .then_some(unsafe { info.ProtocolSpecific.Smb2.Server.Capabilities } & capability != 0)
+}
+
+/// Send the FSCTL `code` with `input` for `file`, expecting no output
+fn fsctl<T>(file: &std::fs::File, code: u32, input: &T) -> std::io::Result<()> {
+    let mut returned = 0;
+    match unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            code,
+            input as *const T as *const c_void,
+            std::mem::size_of::<T>() as u32,
+            std::ptr::null_mut(),
+            0,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    } {
+        0 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn set_sparse(file: &std::fs::File, sparse: bool) -> std::io::Result<()> {
+    let buffer = FILE_SET_SPARSE_BUFFER {
+        SetSparse: u8::from(sparse),
+    };
+    fsctl(file, FSCTL_SET_SPARSE, &buffer)
+}
+
+/// Query the allocated ranges of `file`, a batch at a time until the server stops
+/// replying with `ERROR_MORE_DATA`
+fn query_allocated_ranges(file: &std::fs::File) -> std::io::Result<Vec<Range<u64>>> {
+    let size = file.metadata()?.len();
+    let mut ranges = Vec::new();
+    let mut query = FILE_ALLOCATED_RANGE_BUFFER {
+        FileOffset: 0,
+        Length: size as i64,
+    };
+    let mut batch = [FILE_ALLOCATED_RANGE_BUFFER {
+        FileOffset: 0,
+        Length: 0,
+    }; 64];
+    while query.Length > 0 {
+        let mut returned = 0;
+        let more = match unsafe {
+            DeviceIoControl(
+                file.as_raw_handle(),
+                FSCTL_QUERY_ALLOCATED_RANGES,
+                &query as *const FILE_ALLOCATED_RANGE_BUFFER as *const c_void,
+                std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32,
+                batch.as_mut_ptr() as *mut c_void,
+                std::mem::size_of_val(&batch) as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        } {
+            0 => {
+                let err = std::io::Error::last_os_error();
+                if err.raw_os_error() != Some(ERROR_MORE_DATA as i32) {
+                    return Err(err);
+                }
+                true
+            }
+            _ => false,
+        };
+        match parse_allocated_ranges(&query, &batch, returned, more, &mut ranges) {
+            Some(next) => query = next,
+            None => break,
+        }
+    }
+    Ok(ranges)
+}
+
+/// Append to `ranges` the batch of ranges returned for `query`, `returned` being the amount of
+/// bytes written into `batch`.
+/// Returns the query for the next batch if the server has `more` ranges to return
+fn parse_allocated_ranges(
+    query: &FILE_ALLOCATED_RANGE_BUFFER,
+    batch: &[FILE_ALLOCATED_RANGE_BUFFER],
+    returned: u32,
+    more: bool,
+    ranges: &mut Vec<Range<u64>>,
+) -> Option<FILE_ALLOCATED_RANGE_BUFFER> {
+    let count =
+        (returned as usize / std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>()).min(batch.len());
+    let last = batch[..count].last()?;
+    ranges.extend(
+        batch[..count]
+            .iter()
+            .map(|range| range.FileOffset as u64..(range.FileOffset + range.Length) as u64),
+    );
+    if !more {
+        return None;
+    }
+    // resume the query right after the last range returned
+    let end = last.FileOffset + last.Length;
+    Some(FILE_ALLOCATED_RANGE_BUFFER {
+        FileOffset: end,
+        Length: query.Length - (end - query.FileOffset),
+    })
+    .filter(|next| next.Length > 0)
+}
+
+/// Make the `File` at `path` out of its metadata, not following symlinks.
+///
+/// The target of symlinks is read from their reparse point
+fn to_file(path: PathBuf, metadata: std::fs::Metadata) -> File {
+    let mut metadata = Metadata::from(metadata);
+    if metadata.is_symlink() {
+        match std::fs::read_link(&path) {
+            Ok(target) => metadata.set_symlink(target),
+            Err(err) => error!("Could not read link {}: {}", path.display(), err),
+        }
+    }
+    File { path, metadata }
+}
+
+/// Iterator over the entries of a directory, returned by [`SmbFs::read_dir`].
+///
+/// It borrows the client, as the UNIX one does, although the directory handle doesn't need it
+pub struct ReadDir<'a> {
+    entries: Entries,
+    fs: PhantomData<&'a SmbFs>,
+}
+
+enum Entries {
+    Lazy(Box<std::fs::ReadDir>),
+    Sorted(std::vec::IntoIter<std::io::Result<std::fs::DirEntry>>),
+    /// Shares of the server, already stat'd
+    Files(std::vec::IntoIter<File>),
+}
+
+impl ReadDir<'_> {
+    /// Yield the remaining entries sorted by name, instead of in server order.
+    ///
+    /// This fetches all of them at once.
+    pub fn sorted(self) -> Self {
+        let mut entries: Vec<std::io::Result<std::fs::DirEntry>> = match self.entries {
+            Entries::Lazy(entries) => entries.collect(),
+            Entries::Sorted(entries) => entries.collect(),
+            Entries::Files(files) => {
+                let mut files: Vec<File> = files.collect();
+                files.sort_by_key(|file| file.name());
+                return Self {
+                    entries: Entries::Files(files.into_iter()),
+                    fs: PhantomData,
+                };
+            }
+        };
+        entries.sort_by_key(|entry| entry.as_ref().ok().map(|entry| entry.file_name()));
+        Self {
+            entries: Entries::Sorted(entries.into_iter()),
+            fs: PhantomData,
+        }
+    }
+}
+
+impl Iterator for ReadDir<'_> {
+    type Item = RemoteResult<File>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match &mut self.entries {
+            Entries::Lazy(entries) => entries.next()?,
+            Entries::Sorted(entries) => entries.next()?,
+            Entries::Files(files) => return files.next().map(Ok),
+        };
+        Some(
+            entry
+                .and_then(|entry| {
+                    entry
+                        .metadata()
+                        .map(|metadata| to_file(entry.path(), metadata))
+                })
+                .map_err(|e| error::remote_error(e, RemoteErrorType::StatFailed)),
+        )
+    }
+}
+
+/// Get the names of the disk shares of `server` (`\\server`)
+fn list_shares(server: &Path) -> std::io::Result<Vec<OsString>> {
+    let mut remote_name: Vec<u16> = server.as_os_str().encode_wide().chain(Some(0)).collect();
+    let resource = WNet::NETRESOURCEW {
+        dwScope: WNet::RESOURCE_GLOBALNET,
+        dwType: WNet::RESOURCETYPE_DISK,
+        // RESOURCEDISPLAYTYPE_SERVER
+        dwDisplayType: 2,
+        dwUsage: WNet::RESOURCEUSAGE_CONTAINER,
+        lpLocalName: std::ptr::null_mut(),
+        lpRemoteName: remote_name.as_mut_ptr(),
+        lpComment: std::ptr::null_mut(),
+        lpProvider: std::ptr::null_mut(),
+    };
+    let mut handle: HANDLE = std::ptr::null_mut();
+    let result = unsafe {
+        WNet::WNetOpenEnumW(
+            WNet::RESOURCE_GLOBALNET,
+            WNet::RESOURCETYPE_DISK,
+            0,
+            &resource,
+            &mut handle,
+        )
+    };
+    if result != NO_ERROR {
+        return Err(std::io::Error::from_raw_os_error(result as i32));
+    }
+    // 16 KiB, aligned for NETRESOURCEW
+    let mut buffer = vec![0u64; 2048];
+    let mut shares = Vec::new();
+    let result = loop {
+        let mut count = u32::MAX;
+        let mut size = std::mem::size_of_val(buffer.as_slice()) as u32;
+        match unsafe {
+            WNet::WNetEnumResourceW(handle, &mut count, buffer.as_mut_ptr().cast(), &mut size)
+        } {
+            NO_ERROR => {
+                let resources = unsafe {
+                    std::slice::from_raw_parts(
+                        buffer.as_ptr() as *const WNet::NETRESOURCEW,
+                        count as usize,
+                    )
+                };
+                for resource in resources {
+                    // \\server\share
+                    let name = unsafe { wide_str(resource.lpRemoteName) };
+                    let start = name
+                        .iter()
+                        .rposition(|c| *c == u16::from(b'\\'))
+                        .map_or(0, |i| i + 1);
+                    shares.push(OsString::from_wide(&name[start..]));
+                }
+            }
+            ERROR_NO_MORE_ITEMS => break Ok(shares),
+            err => break Err(std::io::Error::from_raw_os_error(err as i32)),
+        }
+    };
+    unsafe { WNet::WNetCloseEnum(handle) };
+    result
+}
+
+/// Get the targets of the DFS link crossed by `path`, from the cache of the DFS client
+fn dfs_client_info(path: &Path) -> std::io::Result<Vec<SmbDfsTarget>> {
+    let entry_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut buffer: *mut u8 = std::ptr::null_mut();
+    let result = unsafe {
+        NetDfsGetClientInfo(
+            entry_path.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            3,
+            &mut buffer,
+        )
+    };
+    if result != NO_ERROR {
+        return Err(std::io::Error::from_raw_os_error(result as i32));
+    }
+    let info = unsafe { &*(buffer as *const DFS_INFO_3) };
+    let targets = match info.Storage.is_null() {
+        true => Vec::new(),
+        false => unsafe {
+            std::slice::from_raw_parts(info.Storage, info.NumberOfStorages as usize)
+                .iter()
+                .map(|storage| dfs_target(storage))
+                .collect()
+        },
+    };
+    unsafe { NetApiBufferFree(buffer.cast()) };
+    Ok(targets)
+}
+
+/// Make the `SmbDfsTarget` out of the storage of a DFS link
+///
+/// # Safety
+///
+/// The names of `storage` must be null or point to nul terminated wide strings
+unsafe fn dfs_target(storage: &DFS_STORAGE_INFO) -> SmbDfsTarget {
+    let mut path = OsString::from("\\\\");
+    path.push(OsString::from_wide(wide_str(storage.ServerName)));
+    path.push("\\");
+    path.push(OsString::from_wide(wide_str(storage.ShareName)));
+    SmbDfsTarget {
+        path: PathBuf::from(path),
+        active: storage.State & DFS_STORAGE_STATE_ACTIVE != 0,
+    }
+}
+
+/// Get the nul terminated wide string at `ptr`
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a nul terminated wide string
+unsafe fn wide_str<'a>(ptr: *const u16) -> &'a [u16] {
+    if ptr.is_null() {
+        return &[];
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    std::slice::from_raw_parts(ptr, len)
+}
+
+/// List the entries of `dir` matching the SMB search `pattern` with FindFirstFileExW, building them
+/// out of the find data, without stat'ing them one by one.
+///
+/// With `directories_only`, the server is asked to return only the directories
+/// (FindExSearchLimitToDirectories); as it may ignore the hint, the other entries are dropped here
+#[cfg(feature = "find")]
+fn find_files(dir: &Path, pattern: &str, directories_only: bool) -> std::io::Result<Vec<File>> {
+    let query: Vec<u16> = dir
+        .join(pattern)
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+    let mut data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+    let handle = unsafe {
+        FindFirstFileExW(
+            query.as_ptr(),
+            FindExInfoBasic,
+            &mut data as *mut WIN32_FIND_DATAW as *mut c_void,
+            match directories_only {
+                true => FindExSearchLimitToDirectories,
+                false => FindExSearchNameMatch,
+            },
+            std::ptr::null(),
+            FIND_FIRST_EX_LARGE_FETCH,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = std::io::Error::last_os_error();
+        // no entry matches the pattern
+        return match err.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32) {
+            true => Ok(Vec::new()),
+            false => Err(err),
+        };
+    }
+    let mut files = Vec::new();
+    loop {
+        let len = data
+            .cFileName
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(data.cFileName.len());
+        let name = OsString::from_wide(&data.cFileName[..len]);
+        if name != "."
+            && name != ".."
+            && (!directories_only || data.dwFileAttributes & FILE_ATTRIBUTE_DIRECTORY != 0)
+        {
+            let path = dir.join(name);
+            let mut metadata = find_data_to_metadata(&data);
+            if metadata.is_symlink() {
+                match std::fs::read_link(&path) {
+                    Ok(target) => metadata.set_symlink(target),
+                    Err(err) => error!("Could not read link {}: {}", path.display(), err),
+                }
+            }
+            files.push(File { path, metadata });
+        }
+        if unsafe { FindNextFileW(handle, &mut data) } == 0 {
+            break;
+        }
+    }
+    let err = std::io::Error::last_os_error();
+    unsafe { FindClose(handle) };
+    match err.raw_os_error() == Some(ERROR_NO_MORE_FILES as i32) {
+        true => Ok(files),
+        false => Err(err),
+    }
+}
+
+/// Build the metadata of an entry out of its find data.
+///
+/// As for `std::fs::Metadata`, reparse points with a name surrogate tag are reported as symlinks
+#[cfg(feature = "find")]
+fn find_data_to_metadata(data: &WIN32_FIND_DATAW) -> Metadata {
+    /// Bit of the reparse tags of the links to another entry (IsReparseTagNameSurrogate)
+    const NAME_SURROGATE: u32 = 0x2000_0000;
+
+    let file_type = match data.dwFileAttributes {
+        attributes
+            if attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0
+                && data.dwReserved0 & NAME_SURROGATE != 0 =>
+        {
+            FileType::Symlink
+        }
+        attributes if attributes & FILE_ATTRIBUTE_DIRECTORY != 0 => FileType::Directory,
+        _ => FileType::File,
+    };
+    Metadata {
+        accessed: filetime_to_system_time(&data.ftLastAccessTime),
+        created: filetime_to_system_time(&data.ftCreationTime),
+        modified: filetime_to_system_time(&data.ftLastWriteTime),
+        size: (u64::from(data.nFileSizeHigh) << 32) | u64::from(data.nFileSizeLow),
+        ..Metadata::default().file_type(file_type)
+    }
+}
+
+/// Convert a FILETIME (100ns intervals since 1601-01-01) into a `SystemTime`; zero means unset
+#[cfg(feature = "find")]
+fn filetime_to_system_time(time: &FILETIME) -> Option<SystemTime> {
+    intervals_to_system_time((u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime))
+}
+
+/// Convert 100ns intervals since 1601-01-01 into a `SystemTime`; zero means unset
+fn intervals_to_system_time(intervals: u64) -> Option<SystemTime> {
+    /// 100ns intervals between 1601-01-01 and the UNIX epoch
+    const UNIX_EPOCH_INTERVALS: u64 = 116_444_736_000_000_000;
+
+    match intervals {
+        0 => None,
+        intervals if intervals >= UNIX_EPOCH_INTERVALS => Some(
+            SystemTime::UNIX_EPOCH + Duration::from_nanos((intervals - UNIX_EPOCH_INTERVALS) * 100),
+        ),
+        intervals => SystemTime::UNIX_EPOCH.checked_sub(Duration::from_nanos(
+            (UNIX_EPOCH_INTERVALS - intervals) * 100,
+        )),
     }
 }
 
 #[cfg(test)]
-#[cfg(feature = "with-containers")]
 mod test {
 
+    use pretty_assertions::assert_eq;
+
     use super::*;
 
+    fn allocated_range(offset: i64, length: i64) -> FILE_ALLOCATED_RANGE_BUFFER {
+        FILE_ALLOCATED_RANGE_BUFFER {
+            FileOffset: offset,
+            Length: length,
+        }
+    }
+
+    fn remote_protocol_info(version: u16, flags: u32) -> FILE_REMOTE_PROTOCOL_INFO {
+        let mut info: FILE_REMOTE_PROTOCOL_INFO = unsafe { std::mem::zeroed() };
+        info.StructureVersion = version;
+        info.Flags = flags;
+        info
+    }
+
+    #[test]
+    fn should_make_dfs_target() {
+        use windows_sys::Win32::Storage::DistributedFileSystem::DFS_STORAGE_STATE_ONLINE;
+
+        let mut server: Vec<u16> = "fs1".encode_utf16().chain(Some(0)).collect();
+        let mut share: Vec<u16> = "projects".encode_utf16().chain(Some(0)).collect();
+        let storage = DFS_STORAGE_INFO {
+            State: DFS_STORAGE_STATE_ONLINE | DFS_STORAGE_STATE_ACTIVE,
+            ServerName: server.as_mut_ptr(),
+            ShareName: share.as_mut_ptr(),
+        };
+        assert_eq!(
+            unsafe { dfs_target(&storage) },
+            SmbDfsTarget {
+                path: PathBuf::from("\\\\fs1\\projects"),
+                active: true,
+            }
+        );
+    }
+
+    #[test]
+    fn should_get_remote_protocol_flags() {
+        let info = remote_protocol_info(2, REMOTE_PROTOCOL_INFO_FLAG_PRIVACY);
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_PRIVACY),
+            Some(true)
+        );
+        let info = remote_protocol_info(2, 0);
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_PRIVACY),
+            Some(false)
+        );
+        let info = remote_protocol_info(2, REMOTE_PROTOCOL_INFO_FLAG_INTEGRITY);
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_INTEGRITY),
+            Some(true)
+        );
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_PRIVACY),
+            Some(false)
+        );
+        let info = remote_protocol_info(2, REMOTE_PROTOCOL_INFO_FLAG_PERSISTENT_HANDLE);
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_PERSISTENT_HANDLE),
+            Some(true)
+        );
+        // the flags aren't reported before Windows 8
+        let info = remote_protocol_info(1, 0);
+        assert_eq!(
+            protocol_flag(&info, REMOTE_PROTOCOL_INFO_FLAG_PRIVACY),
+            None
+        );
+    }
+
+    #[test]
+    fn should_get_server_capabilities() {
+        let mut info = remote_protocol_info(2, 0);
+        assert_eq!(
+            server_capability(&info, SMB2_GLOBAL_CAP_MULTI_CHANNEL),
+            Some(false)
+        );
+        info.ProtocolSpecific.Smb2.Server.Capabilities = SMB2_GLOBAL_CAP_MULTI_CHANNEL;
+        assert_eq!(
+            server_capability(&info, SMB2_GLOBAL_CAP_MULTI_CHANNEL),
+            Some(true)
+        );
+        // the capabilities aren't reported before Windows 8
+        info.StructureVersion = 1;
+        assert_eq!(
+            server_capability(&info, SMB2_GLOBAL_CAP_MULTI_CHANNEL),
+            None
+        );
+    }
+
+    #[test]
+    fn should_parse_allocated_ranges() {
+        let size = std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32;
+        let query = allocated_range(0, 100);
+        let batch = [
+            allocated_range(0, 10),
+            allocated_range(40, 20),
+            allocated_range(0, 0),
+        ];
+        let mut ranges = Vec::new();
+        // the last range of the buffer wasn't returned
+        let next = parse_allocated_ranges(&query, &batch, 2 * size, true, &mut ranges).unwrap();
+        assert_eq!(ranges, vec![0..10, 40..60]);
+        assert_eq!((next.FileOffset, next.Length), (60, 40));
+        let batch = [allocated_range(80, 20)];
+        assert!(parse_allocated_ranges(&next, &batch, size, false, &mut ranges).is_none());
+        assert_eq!(ranges, vec![0..10, 40..60, 80..100]);
+    }
+
+    #[test]
+    fn should_stop_parsing_allocated_ranges() {
+        let size = std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32;
+        let mut ranges = Vec::new();
+        // no range returned
+        let batch = [allocated_range(0, 0)];
+        assert!(
+            parse_allocated_ranges(&allocated_range(0, 100), &batch, 0, true, &mut ranges)
+                .is_none()
+        );
+        assert!(ranges.is_empty());
+        // the last range reaches the end of the query
+        let batch = [allocated_range(50, 50)];
+        assert!(
+            parse_allocated_ranges(&allocated_range(0, 100), &batch, size, true, &mut ranges)
+                .is_none()
+        );
+        assert_eq!(ranges, vec![50..100]);
+    }
+
+    #[test]
+    #[cfg(feature = "find")]
+    fn should_convert_filetime() {
+        let time = |intervals: u64| FILETIME {
+            dwLowDateTime: intervals as u32,
+            dwHighDateTime: (intervals >> 32) as u32,
+        };
+        assert_eq!(filetime_to_system_time(&time(0)), None);
+        assert_eq!(
+            filetime_to_system_time(&time(116_444_736_000_000_000)),
+            Some(SystemTime::UNIX_EPOCH)
+        );
+        assert_eq!(
+            filetime_to_system_time(&time(116_444_736_000_000_000 + 15)),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(1500))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "find")]
+    fn should_build_metadata_out_of_find_data() {
+        let mut data: WIN32_FIND_DATAW = unsafe { std::mem::zeroed() };
+        data.nFileSizeHigh = 1;
+        data.nFileSizeLow = 2;
+        let metadata = find_data_to_metadata(&data);
+        assert_eq!(metadata.file_type, FileType::File);
+        assert_eq!(metadata.size, (1 << 32) + 2);
+        assert_eq!(metadata.modified, None);
+        data.dwFileAttributes = FILE_ATTRIBUTE_DIRECTORY;
+        assert_eq!(find_data_to_metadata(&data).file_type, FileType::Directory);
+        // IO_REPARSE_TAG_SYMLINK
+        data.dwFileAttributes |= FILE_ATTRIBUTE_REPARSE_POINT;
+        data.dwReserved0 = 0xA000_000C;
+        assert_eq!(find_data_to_metadata(&data).file_type, FileType::Symlink);
+        // IO_REPARSE_TAG_DEDUP is not a link
+        data.dwReserved0 = 0x8000_0013;
+        assert_eq!(find_data_to_metadata(&data).file_type, FileType::Directory);
+    }
+
     #[test]
     #[cfg(feature = "with-containers")]
     fn should_print_working_directory() {
@@ -0,0 +1,11 @@
+/// Encryption level of the connection, as `pavao::SmbEncryptionLevel` on UNIX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmbEncryptionLevel {
+    /// Let the redirector and the share settings decide
+    #[default]
+    None,
+    /// Encrypt if the server supports it; the redirector already does so for the shares requiring it
+    Request,
+    /// Fail to connect if the connection can't be encrypted (`CONNECT_REQUIRE_PRIVACY`)
+    Require,
+}
@@ -0,0 +1,14 @@
+//! # dfs
+//!
+//! Targets of the DFS links
+
+use std::path::PathBuf;
+
+/// Target of a DFS link, returned by `SmbFs::dfs_targets`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmbDfsTarget {
+    /// UNC path of the share the link points to (`\\server\share`)
+    pub path: PathBuf,
+    /// Whether the DFS client currently sends the requests for the link to this target
+    pub active: bool,
+}
@@ -0,0 +1,104 @@
+//! # dialect
+//!
+//! SMB protocol dialects
+
+/// SMB protocol dialect, ordered from the oldest to the newest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SmbDialect {
+    /// SMB1 (NT LM 0.12)
+    Smb1,
+    /// SMB 2.0.2
+    Smb202,
+    /// SMB 2.1
+    Smb210,
+    /// SMB 3.0
+    Smb300,
+    /// SMB 3.0.2
+    Smb302,
+    /// SMB 3.1.1
+    Smb311,
+}
+
+impl SmbDialect {
+    /// Name of the dialect in the `client min protocol` and `client max protocol` Samba parameters
+    #[cfg(smbc_protocols)]
+    pub(crate) fn samba_name(&self) -> &'static str {
+        match self {
+            Self::Smb1 => "NT1",
+            Self::Smb202 => "SMB2_02",
+            Self::Smb210 => "SMB2_10",
+            Self::Smb300 => "SMB3_00",
+            Self::Smb302 => "SMB3_02",
+            Self::Smb311 => "SMB3_11",
+        }
+    }
+
+    /// Get the dialect out of the protocol version reported by `FILE_REMOTE_PROTOCOL_INFO`
+    #[cfg(target_family = "windows")]
+    pub(crate) fn from_protocol_version(major: u16, minor: u16, revision: u16) -> Option<Self> {
+        match (major, minor, revision) {
+            (1, _, _) => Some(Self::Smb1),
+            (2, 0, _) => Some(Self::Smb202),
+            (2, 1, _) => Some(Self::Smb210),
+            (3, 0, 0) => Some(Self::Smb300),
+            (3, 0, _) => Some(Self::Smb302),
+            (3, 1, _) => Some(Self::Smb311),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_order_dialects() {
+        assert!(SmbDialect::Smb1 < SmbDialect::Smb202);
+        assert!(SmbDialect::Smb210 < SmbDialect::Smb300);
+        assert!(SmbDialect::Smb302 < SmbDialect::Smb311);
+        assert_eq!(
+            [SmbDialect::Smb311, SmbDialect::Smb1, SmbDialect::Smb300]
+                .into_iter()
+                .max(),
+            Some(SmbDialect::Smb311)
+        );
+    }
+
+    #[test]
+    #[cfg(target_family = "windows")]
+    fn should_get_dialect_from_protocol_version() {
+        assert_eq!(
+            SmbDialect::from_protocol_version(2, 0, 2),
+            Some(SmbDialect::Smb202)
+        );
+        assert_eq!(
+            SmbDialect::from_protocol_version(2, 1, 0),
+            Some(SmbDialect::Smb210)
+        );
+        assert_eq!(
+            SmbDialect::from_protocol_version(3, 0, 0),
+            Some(SmbDialect::Smb300)
+        );
+        assert_eq!(
+            SmbDialect::from_protocol_version(3, 0, 2),
+            Some(SmbDialect::Smb302)
+        );
+        assert_eq!(
+            SmbDialect::from_protocol_version(3, 1, 1),
+            Some(SmbDialect::Smb311)
+        );
+        assert_eq!(SmbDialect::from_protocol_version(4, 0, 0), None);
+    }
+
+    #[test]
+    #[cfg(smbc_protocols)]
+    fn should_get_samba_name() {
+        assert_eq!(SmbDialect::Smb1.samba_name(), "NT1");
+        assert_eq!(SmbDialect::Smb202.samba_name(), "SMB2_02");
+        assert_eq!(SmbDialect::Smb311.samba_name(), "SMB3_11");
+    }
+}
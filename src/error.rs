@@ -0,0 +1,396 @@
+//! # error
+//!
+//! Translation of the status codes returned by the SMB stack into `RemoteError`.
+//!
+//! On UNIX libsmbclient reports the NTSTATUS returned by the server as an errno value
+//! (e.g. `STATUS_OBJECT_NAME_NOT_FOUND` becomes `ENOENT`, `STATUS_SHARING_VIOLATION` becomes `EBUSY`),
+//! while on Windows the redirector reports it as a Win32 error code (`ERROR_FILE_NOT_FOUND`,
+//! `ERROR_SHARING_VIOLATION`, ...).
+//! The raw code is kept in the message of the returned error, e.g. `... (os error 13) [EACCES]`.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+
+/// An error which may carry the OS status code reported by the SMB stack
+pub(crate) trait StatusCode: fmt::Display {
+    /// Raw OS error code (errno on UNIX, Win32 error on Windows)
+    fn status_code(&self) -> Option<i32>;
+}
+
+impl StatusCode for io::Error {
+    fn status_code(&self) -> Option<i32> {
+        self.raw_os_error()
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl StatusCode for pavao::SmbError {
+    fn status_code(&self) -> Option<i32> {
+        match self {
+            pavao::SmbError::Io(err) => err.raw_os_error(),
+            _ => None,
+        }
+    }
+}
+
+/// Build a `RemoteError` out of `err`, using the most precise `RemoteErrorType` for its status code.
+/// If the status is unknown, `fallback` is used instead
+pub(crate) fn remote_error<E: StatusCode>(err: E, fallback: RemoteErrorType) -> RemoteError {
+    match err.status_code().and_then(lookup) {
+        Some((name, kind)) => RemoteError::new_ex(kind, format!("{err} [{name}]")),
+        None => RemoteError::new_ex(fallback, err),
+    }
+}
+
+/// Failures collected while removing a directory tree, reported at once
+#[derive(Debug, Default)]
+pub(crate) struct RemoveFailures {
+    failures: Vec<(PathBuf, RemoteError)>,
+}
+
+impl RemoveFailures {
+    /// Record the failure to remove `path`
+    pub fn push(&mut self, path: impl Into<PathBuf>, err: RemoteError) {
+        let path = path.into();
+        error!("could not remove {}: {}", path.display(), err);
+        self.failures.push((path, err));
+    }
+
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// Build the result of the removal of `root`.
+    ///
+    /// The error has the kind shared by all the failures (`CouldNotRemoveFile` if they differ)
+    /// and lists each failed entry in its message
+    pub fn into_result(self, root: &Path) -> RemoteResult<()> {
+        let Some((_, first)) = self.failures.first() else {
+            return Ok(());
+        };
+        let kind = match self.failures.iter().all(|(_, err)| err.kind == first.kind) {
+            true => first.kind,
+            false => RemoteErrorType::CouldNotRemoveFile,
+        };
+        let entries: Vec<String> = self
+            .failures
+            .iter()
+            .map(|(path, err)| format!("{}: {}", path.display(), err))
+            .collect();
+        Err(RemoteError::new_ex(
+            kind,
+            format!(
+                "could not remove {} entries under {}: {}",
+                entries.len(),
+                root.display(),
+                entries.join("; ")
+            ),
+        ))
+    }
+}
+
+/// Get the symbolic name and the error type associated to an OS status code
+fn lookup(code: i32) -> Option<(&'static str, RemoteErrorType)> {
+    STATUS_TABLE
+        .iter()
+        .find(|(status, _, _)| *status == code)
+        .map(|(_, name, kind)| (*name, *kind))
+}
+
+#[cfg(target_family = "unix")]
+const STATUS_TABLE: &[(i32, &str, RemoteErrorType)] = &[
+    // STATUS_OBJECT_NAME_NOT_FOUND, STATUS_OBJECT_PATH_NOT_FOUND, STATUS_BAD_NETWORK_NAME
+    (
+        libc::ENOENT,
+        "ENOENT",
+        RemoteErrorType::NoSuchFileOrDirectory,
+    ),
+    // STATUS_NOT_A_DIRECTORY
+    (libc::ENOTDIR, "ENOTDIR", RemoteErrorType::BadFile),
+    // STATUS_FILE_IS_A_DIRECTORY
+    (libc::EISDIR, "EISDIR", RemoteErrorType::BadFile),
+    // STATUS_OBJECT_NAME_INVALID
+    (libc::ENAMETOOLONG, "ENAMETOOLONG", RemoteErrorType::BadFile),
+    // STATUS_ACCESS_DENIED, STATUS_CANNOT_DELETE, STATUS_MEDIA_WRITE_PROTECTED
+    (libc::EACCES, "EACCES", RemoteErrorType::PexError),
+    (libc::EPERM, "EPERM", RemoteErrorType::PexError),
+    (libc::EROFS, "EROFS", RemoteErrorType::PexError),
+    // STATUS_SHARING_VIOLATION, STATUS_FILE_LOCK_CONFLICT
+    (libc::EBUSY, "EBUSY", RemoteErrorType::CouldNotOpenFile),
+    (libc::ETXTBSY, "ETXTBSY", RemoteErrorType::CouldNotOpenFile),
+    // STATUS_OBJECT_NAME_COLLISION
+    (libc::EEXIST, "EEXIST", RemoteErrorType::FileCreateDenied),
+    // STATUS_DIRECTORY_NOT_EMPTY
+    (
+        libc::ENOTEMPTY,
+        "ENOTEMPTY",
+        RemoteErrorType::DirectoryNotEmpty,
+    ),
+    // STATUS_DISK_FULL, STATUS_QUOTA_EXCEEDED
+    (libc::ENOSPC, "ENOSPC", RemoteErrorType::IoError),
+    (libc::EDQUOT, "EDQUOT", RemoteErrorType::IoError),
+    (libc::EIO, "EIO", RemoteErrorType::IoError),
+    // STATUS_CONNECTION_*, STATUS_NETWORK_NAME_DELETED, STATUS_IO_TIMEOUT
+    (
+        libc::ECONNREFUSED,
+        "ECONNREFUSED",
+        RemoteErrorType::ConnectionError,
+    ),
+    (
+        libc::ECONNRESET,
+        "ECONNRESET",
+        RemoteErrorType::ConnectionError,
+    ),
+    (
+        libc::ECONNABORTED,
+        "ECONNABORTED",
+        RemoteErrorType::ConnectionError,
+    ),
+    (
+        libc::EHOSTUNREACH,
+        "EHOSTUNREACH",
+        RemoteErrorType::ConnectionError,
+    ),
+    (
+        libc::ENETUNREACH,
+        "ENETUNREACH",
+        RemoteErrorType::ConnectionError,
+    ),
+    (libc::ENOTCONN, "ENOTCONN", RemoteErrorType::ConnectionError),
+    (
+        libc::ETIMEDOUT,
+        "ETIMEDOUT",
+        RemoteErrorType::ConnectionError,
+    ),
+    (libc::EPIPE, "EPIPE", RemoteErrorType::ConnectionError),
+    // STATUS_NOT_SUPPORTED, STATUS_NOT_IMPLEMENTED
+    (
+        libc::ENOTSUP,
+        "ENOTSUP",
+        RemoteErrorType::UnsupportedFeature,
+    ),
+    (libc::ENOSYS, "ENOSYS", RemoteErrorType::UnsupportedFeature),
+    (libc::EPROTO, "EPROTO", RemoteErrorType::ProtocolError),
+];
+
+#[cfg(target_family = "windows")]
+const STATUS_TABLE: &[(i32, &str, RemoteErrorType)] = {
+    use windows_sys::Win32::Foundation::*;
+
+    &[
+        (
+            ERROR_FILE_NOT_FOUND as i32,
+            "ERROR_FILE_NOT_FOUND",
+            RemoteErrorType::NoSuchFileOrDirectory,
+        ),
+        (
+            ERROR_PATH_NOT_FOUND as i32,
+            "ERROR_PATH_NOT_FOUND",
+            RemoteErrorType::NoSuchFileOrDirectory,
+        ),
+        (
+            ERROR_BAD_NET_NAME as i32,
+            "ERROR_BAD_NET_NAME",
+            RemoteErrorType::NoSuchFileOrDirectory,
+        ),
+        (
+            ERROR_DIRECTORY as i32,
+            "ERROR_DIRECTORY",
+            RemoteErrorType::BadFile,
+        ),
+        (
+            ERROR_INVALID_NAME as i32,
+            "ERROR_INVALID_NAME",
+            RemoteErrorType::BadFile,
+        ),
+        (
+            ERROR_FILENAME_EXCED_RANGE as i32,
+            "ERROR_FILENAME_EXCED_RANGE",
+            RemoteErrorType::BadFile,
+        ),
+        (
+            ERROR_ACCESS_DENIED as i32,
+            "ERROR_ACCESS_DENIED",
+            RemoteErrorType::PexError,
+        ),
+        (
+            ERROR_WRITE_PROTECT as i32,
+            "ERROR_WRITE_PROTECT",
+            RemoteErrorType::PexError,
+        ),
+        (
+            ERROR_SHARING_VIOLATION as i32,
+            "ERROR_SHARING_VIOLATION",
+            RemoteErrorType::CouldNotOpenFile,
+        ),
+        (
+            ERROR_LOCK_VIOLATION as i32,
+            "ERROR_LOCK_VIOLATION",
+            RemoteErrorType::CouldNotOpenFile,
+        ),
+        (
+            ERROR_FILE_EXISTS as i32,
+            "ERROR_FILE_EXISTS",
+            RemoteErrorType::FileCreateDenied,
+        ),
+        (
+            ERROR_ALREADY_EXISTS as i32,
+            "ERROR_ALREADY_EXISTS",
+            RemoteErrorType::FileCreateDenied,
+        ),
+        (
+            ERROR_DIR_NOT_EMPTY as i32,
+            "ERROR_DIR_NOT_EMPTY",
+            RemoteErrorType::DirectoryNotEmpty,
+        ),
+        (
+            ERROR_DISK_FULL as i32,
+            "ERROR_DISK_FULL",
+            RemoteErrorType::IoError,
+        ),
+        (
+            ERROR_HANDLE_DISK_FULL as i32,
+            "ERROR_HANDLE_DISK_FULL",
+            RemoteErrorType::IoError,
+        ),
+        (
+            ERROR_LOGON_FAILURE as i32,
+            "ERROR_LOGON_FAILURE",
+            RemoteErrorType::AuthenticationFailed,
+        ),
+        (
+            ERROR_INVALID_PASSWORD as i32,
+            "ERROR_INVALID_PASSWORD",
+            RemoteErrorType::AuthenticationFailed,
+        ),
+        (
+            ERROR_ACCOUNT_RESTRICTION as i32,
+            "ERROR_ACCOUNT_RESTRICTION",
+            RemoteErrorType::AuthenticationFailed,
+        ),
+        (
+            ERROR_BAD_NETPATH as i32,
+            "ERROR_BAD_NETPATH",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_NETNAME_DELETED as i32,
+            "ERROR_NETNAME_DELETED",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_UNEXP_NET_ERR as i32,
+            "ERROR_UNEXP_NET_ERR",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_SEM_TIMEOUT as i32,
+            "ERROR_SEM_TIMEOUT",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_NETWORK_UNREACHABLE as i32,
+            "ERROR_NETWORK_UNREACHABLE",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_CONNECTION_ABORTED as i32,
+            "ERROR_CONNECTION_ABORTED",
+            RemoteErrorType::ConnectionError,
+        ),
+        (
+            ERROR_NOT_SUPPORTED as i32,
+            "ERROR_NOT_SUPPORTED",
+            RemoteErrorType::UnsupportedFeature,
+        ),
+    ]
+};
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[cfg(target_family = "unix")]
+    const NOT_FOUND: i32 = libc::ENOENT;
+    #[cfg(target_family = "windows")]
+    const NOT_FOUND: i32 = windows_sys::Win32::Foundation::ERROR_FILE_NOT_FOUND as i32;
+    #[cfg(target_family = "unix")]
+    const ACCESS_DENIED: i32 = libc::EACCES;
+    #[cfg(target_family = "windows")]
+    const ACCESS_DENIED: i32 = windows_sys::Win32::Foundation::ERROR_ACCESS_DENIED as i32;
+
+    #[test]
+    fn should_map_status_to_precise_error() {
+        let err = remote_error(
+            io::Error::from_raw_os_error(NOT_FOUND),
+            RemoteErrorType::StatFailed,
+        );
+        assert_eq!(err.kind, RemoteErrorType::NoSuchFileOrDirectory);
+        let err = remote_error(
+            io::Error::from_raw_os_error(ACCESS_DENIED),
+            RemoteErrorType::StatFailed,
+        );
+        assert_eq!(err.kind, RemoteErrorType::PexError);
+    }
+
+    #[test]
+    fn should_keep_raw_status_in_message() {
+        let err = remote_error(
+            io::Error::from_raw_os_error(ACCESS_DENIED),
+            RemoteErrorType::ProtocolError,
+        );
+        let msg = err.msg.unwrap();
+        assert!(msg.contains(&format!("os error {ACCESS_DENIED}")));
+        assert!(msg.ends_with(']'));
+    }
+
+    #[test]
+    fn should_use_fallback_for_unknown_status() {
+        let err = remote_error(io::Error::other("oops"), RemoteErrorType::ProtocolError);
+        assert_eq!(err.kind, RemoteErrorType::ProtocolError);
+        assert_eq!(err.msg.as_deref(), Some("oops"));
+    }
+
+    #[test]
+    fn should_aggregate_remove_failures() {
+        let mut failures = RemoveFailures::default();
+        failures.push("/dir/a.txt", RemoteError::new(RemoteErrorType::PexError));
+        failures.push("/dir/b.txt", RemoteError::new(RemoteErrorType::PexError));
+        assert_eq!(failures.len(), 2);
+        let err = failures.into_result(Path::new("/dir")).unwrap_err();
+        assert_eq!(err.kind, RemoteErrorType::PexError);
+        let msg = err.msg.unwrap();
+        assert!(msg.contains("could not remove 2 entries under /dir"));
+        assert!(msg.contains("/dir/a.txt"));
+        assert!(msg.contains("/dir/b.txt"));
+
+        let mut failures = RemoveFailures::default();
+        failures.push("/dir/a.txt", RemoteError::new(RemoteErrorType::PexError));
+        failures.push("/dir", RemoteError::new(RemoteErrorType::DirectoryNotEmpty));
+        assert_eq!(
+            failures.into_result(Path::new("/dir")).unwrap_err().kind,
+            RemoteErrorType::CouldNotRemoveFile
+        );
+        assert!(RemoveFailures::default()
+            .into_result(Path::new("/dir"))
+            .is_ok());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn should_map_smb_error() {
+        let err = remote_error(
+            pavao::SmbError::Io(io::Error::from_raw_os_error(libc::EBUSY)),
+            RemoteErrorType::IoError,
+        );
+        assert_eq!(err.kind, RemoteErrorType::CouldNotOpenFile);
+        let err = remote_error(pavao::SmbError::BadValue, RemoteErrorType::IoError);
+        assert_eq!(err.kind, RemoteErrorType::IoError);
+    }
+}
@@ -0,0 +1,470 @@
+//! # find
+//!
+//! Lazy recursive search of the entries of a directory tree
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use remotefs::fs::File;
+use remotefs::RemoteResult;
+use wildmatch::WildMatch;
+
+/// Characters which have a special meaning in a SMB search pattern, but not in a `WildMatch`
+const SMB_WILDCARDS: &[char] = &['<', '>', '"', '/', '\\'];
+
+/// Type of the entries reported by [`Find`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindType {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// How [`Find`] deals with symlinks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Report symlinks without descending into them
+    #[default]
+    Report,
+    /// Don't report symlinks at all
+    Skip,
+    /// Report symlinks and descend into the ones pointing to a directory, visiting each directory once
+    Follow,
+}
+
+/// Options for [`Find`].
+///
+/// By default every entry whose name matches the pattern is reported, at any depth.
+#[derive(Debug, Clone)]
+pub struct FindOptions {
+    pattern: String,
+    matcher: WildMatch,
+    max_depth: Option<usize>,
+    file_type: Option<FindType>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    symlinks: SymlinkPolicy,
+}
+
+impl FindOptions {
+    /// Search for the entries whose name matches the wildcard `pattern` (`*` and `?`)
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            matcher: WildMatch::new(pattern),
+            max_depth: None,
+            file_type: None,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            symlinks: SymlinkPolicy::default(),
+        }
+    }
+
+    /// Don't descend more than `depth` levels below the search root; `1` searches only its entries
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Report only the entries of type `file_type`
+    pub fn file_type(mut self, file_type: FindType) -> Self {
+        self.file_type = Some(file_type);
+        self
+    }
+
+    /// Report only the entries at least `size` bytes long
+    pub fn min_size(mut self, size: u64) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// Report only the entries at most `size` bytes long
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Report only the entries modified after `time`
+    pub fn modified_after(mut self, time: SystemTime) -> Self {
+        self.modified_after = Some(time);
+        self
+    }
+
+    /// Report only the entries modified before `time`
+    pub fn modified_before(mut self, time: SystemTime) -> Self {
+        self.modified_before = Some(time);
+        self
+    }
+
+    /// Set how symlinks are handled. See [`SymlinkPolicy`]
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Whether the entries found at `depth` can be searched for children
+    fn can_descend(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth < max_depth)
+    }
+
+    /// Whether `file` satisfies all the options
+    fn matches(&self, file: &File) -> bool {
+        let metadata = file.metadata();
+        let file_type = match () {
+            _ if file.is_symlink() => FindType::Symlink,
+            _ if file.is_dir() => FindType::Directory,
+            _ => FindType::File,
+        };
+        self.file_type.is_none_or(|t| t == file_type)
+            && self.min_size.is_none_or(|size| metadata.size >= size)
+            && self.max_size.is_none_or(|size| metadata.size <= size)
+            && self
+                .modified_after
+                .is_none_or(|time| metadata.modified.is_some_and(|m| m > time))
+            && self
+                .modified_before
+                .is_none_or(|time| metadata.modified.is_some_and(|m| m < time))
+            && self.matcher.matches(&file.name())
+    }
+}
+
+/// A file system which can be searched by [`Find`]
+pub(crate) trait Walk {
+    /// List the entries of `dir`.
+    /// If `pattern` is set, the entries whose name doesn't match it may be omitted,
+    /// but for the directories if they are going to be searched too (`descend`)
+    fn list(&mut self, dir: &Path, pattern: Option<&str>, descend: bool)
+        -> RemoteResult<Vec<File>>;
+
+    /// Get the metadata of the entry at `path`, following symlinks
+    fn stat_target(&mut self, path: &Path) -> RemoteResult<File>;
+}
+
+/// Iterator over the entries of a directory tree matching [`FindOptions`].
+///
+/// Directories are listed only when the iterator reaches them, so results are reported
+/// as soon as they are found. Listing failures are reported as errors, then the search goes on.
+pub struct Find<'a> {
+    fs: &'a mut dyn Walk,
+    options: FindOptions,
+    /// directories still to be listed, with their depth
+    pending: Vec<(PathBuf, usize)>,
+    /// entries of the directory being visited
+    entries: std::vec::IntoIter<File>,
+    /// depth of `entries`
+    depth: usize,
+    /// directories already queued, to break symlink loops
+    visited: HashSet<PathBuf>,
+}
+
+impl<'a> Find<'a> {
+    pub(crate) fn new(fs: &'a mut dyn Walk, root: &Path, options: FindOptions) -> Self {
+        Self {
+            fs,
+            options,
+            pending: vec![(root.to_path_buf(), 0)],
+            entries: Vec::new().into_iter(),
+            depth: 0,
+            visited: HashSet::from([normalize(root)]),
+        }
+    }
+
+    /// The pattern to hand to the server when listing a directory.
+    ///
+    /// The pattern can be evaluated by the server only if it has the same meaning in SMB;
+    /// results are matched again anyway.
+    fn server_pattern(&self) -> Option<&str> {
+        let pattern = self.options.pattern.as_str();
+        (!pattern.contains(SMB_WILDCARDS)).then_some(pattern)
+    }
+
+    /// Queue `file` for listing if it must be searched, then tell whether it must be reported
+    fn visit(&mut self, file: &File) -> RemoteResult<bool> {
+        if file.is_symlink() {
+            match self.options.symlinks {
+                SymlinkPolicy::Skip => return Ok(false),
+                SymlinkPolicy::Report => {}
+                SymlinkPolicy::Follow if self.options.can_descend(self.depth) => {
+                    let target = match file.metadata().symlink.as_deref() {
+                        Some(target) => file
+                            .path()
+                            .parent()
+                            .map(|parent| parent.join(target))
+                            .unwrap_or_else(|| target.to_path_buf()),
+                        None => file.path().to_path_buf(),
+                    };
+                    if self.fs.stat_target(file.path())?.is_dir()
+                        && self.visited.insert(normalize(&target))
+                    {
+                        self.pending.push((file.path().to_path_buf(), self.depth));
+                    }
+                }
+                SymlinkPolicy::Follow => {}
+            }
+        } else if file.is_dir()
+            && self.options.can_descend(self.depth)
+            && self.visited.insert(normalize(file.path()))
+        {
+            self.pending.push((file.path().to_path_buf(), self.depth));
+        }
+        Ok(self.options.matches(file))
+    }
+}
+
+impl Iterator for Find<'_> {
+    type Item = RemoteResult<File>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(file) = self.entries.next() {
+                match self.visit(&file) {
+                    Ok(true) => return Some(Ok(file)),
+                    Ok(false) => continue,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            let (dir, depth) = self.pending.pop()?;
+            let pattern = self.server_pattern().map(str::to_string);
+            let descend = self.options.can_descend(depth + 1);
+            match self.fs.list(&dir, pattern.as_deref(), descend) {
+                Ok(entries) => {
+                    self.entries = entries.into_iter();
+                    self.depth = depth + 1;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Resolve the `.` and `..` components of `path` without accessing the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+    use remotefs::fs::{FileType, Metadata};
+    use remotefs::{RemoteError, RemoteErrorType};
+
+    use super::*;
+
+    /// In-memory tree: every directory maps to its entries
+    #[derive(Default)]
+    struct Tree {
+        dirs: HashMap<PathBuf, Vec<File>>,
+        listed: Vec<(PathBuf, Option<String>, bool)>,
+    }
+
+    impl Tree {
+        fn add(&mut self, path: &str, metadata: Metadata) -> &mut Self {
+            let path = PathBuf::from(path);
+            if metadata.is_dir() {
+                self.dirs.entry(path.clone()).or_default();
+            }
+            self.dirs
+                .entry(path.parent().unwrap().to_path_buf())
+                .or_default()
+                .push(File { path, metadata });
+            self
+        }
+    }
+
+    impl Walk for Tree {
+        fn list(
+            &mut self,
+            dir: &Path,
+            pattern: Option<&str>,
+            descend: bool,
+        ) -> RemoteResult<Vec<File>> {
+            self.listed
+                .push((dir.to_path_buf(), pattern.map(str::to_string), descend));
+            let matcher = WildMatch::new(pattern.unwrap_or("*"));
+            self.dirs
+                .get(dir)
+                .map(|files| {
+                    files
+                        .iter()
+                        .filter(|file| {
+                            matcher.matches(&file.name())
+                                || (descend && (file.is_dir() || file.is_symlink()))
+                        })
+                        .cloned()
+                        .collect()
+                })
+                .ok_or_else(|| RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory))
+        }
+
+        fn stat_target(&mut self, path: &Path) -> RemoteResult<File> {
+            let file = self
+                .dirs
+                .values()
+                .flatten()
+                .find(|file| file.path() == path)
+                .cloned()
+                .unwrap();
+            let target = file
+                .path()
+                .parent()
+                .unwrap()
+                .join(file.metadata().symlink.as_deref().unwrap_or(file.path()));
+            let metadata = match self.dirs.contains_key(&normalize(&target)) {
+                true => dir(),
+                false => Metadata::default(),
+            };
+            Ok(File {
+                path: path.to_path_buf(),
+                metadata,
+            })
+        }
+    }
+
+    fn dir() -> Metadata {
+        Metadata::default().file_type(FileType::Directory)
+    }
+
+    fn file(size: u64) -> Metadata {
+        Metadata::default()
+            .size(size)
+            .modified(SystemTime::UNIX_EPOCH + Duration::from_secs(size))
+    }
+
+    fn link(target: &str) -> Metadata {
+        Metadata::default()
+            .file_type(FileType::Symlink)
+            .symlink(target)
+    }
+
+    fn tree() -> Tree {
+        let mut tree = Tree::default();
+        tree.add("/root/a.txt", file(10))
+            .add("/root/b.log", file(20))
+            .add("/root/sub", dir())
+            .add("/root/sub/c.txt", file(30))
+            .add("/root/sub/deep", dir())
+            .add("/root/sub/deep/d.txt", file(40))
+            .add("/root/sub/up", link(".."));
+        tree
+    }
+
+    fn find(tree: &mut Tree, options: FindOptions) -> Vec<String> {
+        let mut names: Vec<String> = Find::new(tree, Path::new("/root"), options)
+            .map(|file| file.unwrap().path().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn should_find_matching_entries_recursively() {
+        let mut tree = tree();
+        assert_eq!(
+            find(&mut tree, FindOptions::new("*.txt")),
+            vec!["/root/a.txt", "/root/sub/c.txt", "/root/sub/deep/d.txt"]
+        );
+        // the pattern is pushed to the server at any depth
+        assert!(tree
+            .listed
+            .iter()
+            .all(|(_, pattern, descend)| pattern.as_deref() == Some("*.txt") && *descend));
+        assert_eq!(tree.listed.len(), 3);
+    }
+
+    #[test]
+    fn should_not_push_smb_wildcards_to_server() {
+        let mut tree = tree();
+        assert_eq!(
+            find(&mut tree, FindOptions::new("a.txt<").max_depth(1)),
+            Vec::<String>::new()
+        );
+        assert_eq!(tree.listed, vec![(PathBuf::from("/root"), None, false)]);
+    }
+
+    #[test]
+    fn should_limit_depth_and_push_pattern_to_server() {
+        let mut tree = tree();
+        assert_eq!(
+            find(&mut tree, FindOptions::new("*.txt").max_depth(2)),
+            vec!["/root/a.txt", "/root/sub/c.txt"]
+        );
+        assert_eq!(
+            tree.listed,
+            vec![
+                (PathBuf::from("/root"), Some("*.txt".to_string()), true),
+                (PathBuf::from("/root/sub"), Some("*.txt".to_string()), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_filter_by_type_size_and_time() {
+        assert_eq!(
+            find(
+                &mut tree(),
+                FindOptions::new("*").file_type(FindType::Directory)
+            ),
+            vec!["/root/sub", "/root/sub/deep"]
+        );
+        assert_eq!(
+            find(&mut tree(), FindOptions::new("*").min_size(20).max_size(30)),
+            vec!["/root/b.log", "/root/sub/c.txt"]
+        );
+        assert_eq!(
+            find(
+                &mut tree(),
+                FindOptions::new("*")
+                    .modified_after(SystemTime::UNIX_EPOCH + Duration::from_secs(30))
+            ),
+            vec!["/root/sub/deep/d.txt"]
+        );
+    }
+
+    #[test]
+    fn should_apply_symlink_policy() {
+        assert_eq!(
+            find(&mut tree(), FindOptions::new("up")),
+            vec!["/root/sub/up"]
+        );
+        assert!(find(
+            &mut tree(),
+            FindOptions::new("up").symlinks(SymlinkPolicy::Skip)
+        )
+        .is_empty());
+        // the link points to an already visited directory
+        assert_eq!(
+            find(
+                &mut tree(),
+                FindOptions::new("*.txt").symlinks(SymlinkPolicy::Follow)
+            ),
+            vec!["/root/a.txt", "/root/sub/c.txt", "/root/sub/deep/d.txt"]
+        );
+    }
+
+    #[test]
+    fn should_report_listing_errors() {
+        let mut tree = Tree::default();
+        let mut find = Find::new(&mut tree, Path::new("/missing"), FindOptions::new("*"));
+        assert!(find.next().unwrap().is_err());
+        assert!(find.next().is_none());
+    }
+}
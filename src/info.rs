@@ -0,0 +1,21 @@
+//! # info
+//!
+//! SMB specific metadata of the entries
+
+/// Metadata of an entry which `remotefs::fs::Metadata` can't represent, returned by `SmbFs::file_info`
+/// for any path, including the ones returned by `stat`, `list_dir` and `read_dir`.
+///
+/// On UNIX the file ID may be a hash of the path and the volume serial identifies the share rather
+/// than the volume; see `SmbFs::file_info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SmbFileInfo {
+    /// Amount of hard links to the entry, if reported by the server
+    pub links: Option<u64>,
+    /// File ID assigned by the server (FileInternalInformation), unique within the volume;
+    /// two paths with the same ID are hard links to the same file. It is kept across renames
+    pub file_id: Option<u64>,
+    /// 128-bit file ID (FileIdInformation), for the file systems whose IDs don't fit in 64 bits (ReFS)
+    pub file_id_128: Option<u128>,
+    /// Serial number of the volume the entry is stored on, which scopes the file IDs
+    pub volume_serial: Option<u64>,
+}
@@ -69,12 +69,35 @@
 #[macro_use]
 extern crate log;
 
+mod atomic;
+mod cache;
 mod client;
+mod dfs;
+mod dialect;
+mod error;
+#[cfg(feature = "find")]
+mod find;
+mod info;
+mod liveness;
+mod transfer;
 
+pub use atomic::TempNaming;
+pub use cache::MetadataCache;
 #[cfg(target_family = "unix")]
-pub use client::{SmbCredentials, SmbEncryptionLevel, SmbFs, SmbOptions, SmbShareMode};
+pub use client::{
+    ReadDir, SmbContextOptions, SmbCredentials, SmbEncryptionLevel, SmbFs, SmbOptions,
+    SmbShareMode, SmbSigning,
+};
 #[cfg(target_family = "windows")]
-pub use client::{SmbCredentials, SmbFs};
+pub use client::{ReadDir, SmbCredentials, SmbEncryptionLevel, SmbFs};
+pub use dfs::SmbDfsTarget;
+pub use dialect::SmbDialect;
+#[cfg(feature = "find")]
+pub use find::{Find, FindOptions, FindType, SymlinkPolicy};
+pub use info::SmbFileInfo;
+pub use transfer::{
+    CancellationToken, ProgressCallback, RateLimiter, TransferOptions, TransferProgress,
+};
 
 // -- utils
 #[cfg(target_family = "unix")]
@@ -0,0 +1,121 @@
+//! # liveness
+//!
+//! Connection liveness tracking for `SmbFs`
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use remotefs::{RemoteError, RemoteErrorType};
+
+/// Default time after which an idle connection is probed before being used (60 seconds)
+pub(crate) const DEFAULT_IDLE_PROBE: Duration = Duration::from_secs(60);
+
+/// Tracks whether the connection is alive out of the outcome of the operations.
+///
+/// The connection is seen alive whenever an operation succeeds, and lost as soon as one fails
+/// with a connection error; it must then be probed before the next operation, as it must be
+/// once no operation has succeeded for longer than the idle probe interval.
+///
+/// Nothing is sent while the connection is idle: libsmbclient has no SMB2 ECHO and its context
+/// can't be used from a background thread, so the connection is only probed before being used.
+#[derive(Debug)]
+pub(crate) struct Liveness {
+    idle_probe: Option<Duration>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    alive: bool,
+    last_seen: Option<Instant>,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            idle_probe: Some(DEFAULT_IDLE_PROBE),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl Liveness {
+    /// Set the interval after which an idle connection must be probed. `None` disables idle probes
+    pub fn set_idle_probe(&mut self, interval: Option<Duration>) {
+        self.idle_probe = interval;
+    }
+
+    /// Whether the connection must be probed before being used
+    pub fn needs_probe(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        !state.alive
+            || self.idle_probe.is_some_and(|interval| {
+                state
+                    .last_seen
+                    .is_none_or(|last_seen| last_seen.elapsed() >= interval)
+            })
+    }
+
+    /// Mark the connection as alive, after the server answered a request
+    pub fn seen(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.alive = true;
+        state.last_seen = Some(Instant::now());
+    }
+
+    /// Mark the connection as lost
+    pub fn lost(&self) {
+        self.state.lock().unwrap().alive = false;
+    }
+
+    /// Mark the connection as lost if `err` is a connection error; then return `err`
+    pub fn observe(&self, err: RemoteError) -> RemoteError {
+        if matches!(
+            err.kind,
+            RemoteErrorType::ConnectionError
+                | RemoteErrorType::NotConnected
+                | RemoteErrorType::AuthenticationFailed
+        ) {
+            debug!("connection lost: {}", err);
+            self.lost();
+        }
+        err
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_probe_until_seen() {
+        let liveness = Liveness::default();
+        assert!(liveness.needs_probe());
+        liveness.seen();
+        assert!(!liveness.needs_probe());
+    }
+
+    #[test]
+    fn should_probe_after_connection_error() {
+        let liveness = Liveness::default();
+        liveness.seen();
+        let err = liveness.observe(RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory));
+        assert_eq!(err.kind, RemoteErrorType::NoSuchFileOrDirectory);
+        assert!(!liveness.needs_probe());
+        liveness.observe(RemoteError::new(RemoteErrorType::ConnectionError));
+        assert!(liveness.needs_probe());
+    }
+
+    #[test]
+    fn should_probe_idle_connection() {
+        let mut liveness = Liveness::default();
+        liveness.set_idle_probe(Some(Duration::ZERO));
+        liveness.seen();
+        assert!(liveness.needs_probe());
+        liveness.set_idle_probe(None);
+        assert!(!liveness.needs_probe());
+    }
+}
@@ -0,0 +1,329 @@
+//! # transfer
+//!
+//! Chunked transfer engine used by the `*_file` helpers of `SmbFs`
+
+mod progress;
+#[cfg(any(target_family = "windows", test))]
+mod ranges;
+#[cfg(any(target_family = "unix", test))]
+mod reopen;
+mod resume;
+#[cfg(any(target_family = "unix", test))]
+mod sparse;
+mod throttle;
+
+use std::io::{self, Read};
+use std::thread;
+
+pub(crate) use self::progress::Progress;
+pub use self::progress::{CancellationToken, ProgressCallback, TransferProgress};
+#[cfg(target_family = "windows")]
+pub(crate) use self::ranges::{download_ranges, upload_ranges};
+#[cfg(target_family = "unix")]
+pub(crate) use self::reopen::Reopening;
+pub(crate) use self::resume::resume_offset;
+#[cfg(target_family = "unix")]
+pub(crate) use self::sparse::Sparse;
+pub use self::throttle::RateLimiter;
+pub(crate) use self::throttle::Throttled;
+
+/// Default size of a single transfer chunk (1 MiB)
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// Smallest chunk size accepted by [`TransferOptions`] (64 KiB)
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+/// Largest chunk size accepted by [`TransferOptions`] (8 MiB).
+///
+/// This matches the largest READ/WRITE size negotiated by common SMB2/3 servers;
+/// larger chunks are split by the underlying stack into requests of the negotiated max size.
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// Default amount of chunks in flight
+const DEFAULT_CONCURRENCY: usize = 4;
+/// Default amount of chunks buffered ahead of the remote handle
+const DEFAULT_READ_AHEAD: usize = 4;
+/// Default amount of times a write is resumed after the connection has been lost
+const DEFAULT_RESUME_ATTEMPTS: usize = 3;
+
+/// Options for transfers performed by `open_file`, `create_file` and `append_file`.
+///
+/// Files are moved in chunks of `chunk_size` bytes (1 MiB by default).
+///
+/// On Windows, up to `concurrency` chunks (4 by default) are read or written at the same time,
+/// each one by a worker with its own handle, so that several READ/WRITE requests are outstanding.
+///
+/// Concurrent requests are Windows only: libsmbclient serves a single request at a time on a
+/// context, so on UNIX the remote file is read or written one chunk after the other, with a
+/// single request in flight, whatever the options. `read_ahead` (4 chunks by default) only lets
+/// the local reader or writer run on a worker thread alongside the remote requests; it doesn't
+/// raise the throughput of the remote side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferOptions {
+    pub(crate) chunk_size: usize,
+    pub(crate) concurrency: usize,
+    pub(crate) read_ahead: usize,
+    pub(crate) verify_tail: usize,
+    pub(crate) resume_attempts: usize,
+    pub(crate) sparse: bool,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            read_ahead: DEFAULT_READ_AHEAD,
+            verify_tail: 0,
+            resume_attempts: DEFAULT_RESUME_ATTEMPTS,
+            sparse: false,
+        }
+    }
+}
+
+impl TransferOptions {
+    /// Set the size of a single chunk.
+    /// The value is clamped between 64 KiB and 8 MiB
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Set the amount of chunks which can be in flight at the same time on Windows (at least 1)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the amount of chunks buffered between the local reader or writer and the remote handle
+    /// on UNIX (at least 1)
+    pub fn read_ahead(mut self, chunks: usize) -> Self {
+        self.read_ahead = chunks.max(1);
+        self
+    }
+
+    /// When resuming a transfer, compare the last `bytes` already transferred with the source
+    /// and start over if they differ. Disabled (`0`) by default
+    pub fn verify_tail(mut self, bytes: usize) -> Self {
+        self.verify_tail = bytes;
+        self
+    }
+
+    /// When the connection is lost during an upload on UNIX, reopen the remote file once the
+    /// session is re-established and resume writing where it stopped, up to `attempts` times
+    /// in a row (3 by default; `0` disables it).
+    ///
+    /// This is a best-effort resume, **not** a durable handle reconnect: libsmbclient requests no
+    /// durable nor persistent handle, so the file is reopened as a new handle, nothing is reclaimed
+    /// from the previous session (locks, leases, delete-on-close), the open fails if another client
+    /// holds the file in a conflicting share mode meanwhile, and the changes made to the file by
+    /// other clients in the meantime aren't detected. On Windows the redirector reclaims its durable
+    /// and persistent handles by itself, and this option doesn't apply.
+    pub fn resume_attempts(mut self, attempts: usize) -> Self {
+        self.resume_attempts = attempts;
+        self
+    }
+
+    /// When uploading, skip the chunks made only of zeros instead of writing them, leaving holes
+    /// in the remote file. Disabled by default.
+    ///
+    /// Holes take no space only on sparse files: the Windows client marks the files it creates
+    /// as sparse, while Samba servers leave holes wherever their file system supports them.
+    /// Ignored by `append_file` on UNIX.
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+}
+
+/// Copy `reader` into `writer`, running the writer on a worker thread.
+///
+/// Used for downloads: `reader` is the remote file, which must stay on the calling thread,
+/// while up to `read_ahead` chunks are queued towards the local writer.
+#[cfg(any(target_family = "unix", test))]
+pub(crate) fn pull<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    options: &TransferOptions,
+    progress: &mut Progress,
+) -> io::Result<u64>
+where
+    R: Read + ?Sized,
+    W: io::Write + Send + ?Sized,
+{
+    thread::scope(|scope| {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<u8>>(options.read_ahead);
+        let worker = scope.spawn(move || -> io::Result<()> {
+            for chunk in rx {
+                writer.write_all(&chunk)?;
+            }
+            writer.flush()
+        });
+        let mut transferred = 0;
+        loop {
+            let chunk = read_chunk(reader, options.chunk_size)?;
+            if chunk.is_empty() {
+                break;
+            }
+            transferred += chunk.len() as u64;
+            progress.advance(chunk.len() as u64)?;
+            // if the worker hung up, its error is reported on join
+            if tx.send(chunk).is_err() {
+                break;
+            }
+        }
+        drop(tx);
+        join(worker)?;
+        Ok(transferred)
+    })
+}
+
+/// Copy `reader` into `writer`, running the reader on a worker thread.
+///
+/// Used for uploads: `writer` is the remote file, which must stay on the calling thread,
+/// while the local reader fills up to `read_ahead` chunks ahead.
+#[cfg(any(target_family = "unix", test))]
+pub(crate) fn push<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    options: &TransferOptions,
+    progress: &mut Progress,
+) -> io::Result<u64>
+where
+    R: Read + Send + ?Sized,
+    W: io::Write + ?Sized,
+{
+    thread::scope(|scope| {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<u8>>(options.read_ahead);
+        let chunk_size = options.chunk_size;
+        let worker = scope.spawn(move || -> io::Result<()> {
+            loop {
+                let chunk = read_chunk(reader, chunk_size)?;
+                if chunk.is_empty() || tx.send(chunk).is_err() {
+                    return Ok(());
+                }
+            }
+        });
+        let mut transferred = 0;
+        for chunk in rx.iter() {
+            writer.write_all(&chunk)?;
+            transferred += chunk.len() as u64;
+            progress.advance(chunk.len() as u64)?;
+        }
+        join(worker)?;
+        writer.flush()?;
+        Ok(transferred)
+    })
+}
+
+/// Read from `reader` until `chunk_size` bytes have been read or EOF is reached
+fn read_chunk<R: Read + ?Sized>(reader: &mut R, chunk_size: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; chunk_size];
+    let mut filled = 0;
+    while filled < chunk_size {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    buf.truncate(filled);
+    Ok(buf)
+}
+
+/// Whether `buf` is made only of zeros
+fn is_zero(buf: &[u8]) -> bool {
+    buf.iter().all(|byte| *byte == 0)
+}
+
+fn join<T>(handle: thread::ScopedJoinHandle<'_, io::Result<T>>) -> io::Result<T> {
+    handle
+        .join()
+        .map_err(|_| io::Error::other("transfer worker panicked"))?
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    pub fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    pub fn progress() -> Progress {
+        Progress::new(None, CancellationToken::default(), None)
+    }
+
+    pub fn options() -> TransferOptions {
+        TransferOptions::default()
+            .chunk_size(MIN_CHUNK_SIZE)
+            .concurrency(3)
+            .read_ahead(3)
+    }
+
+    #[test]
+    fn should_clamp_transfer_options() {
+        let options = TransferOptions::default()
+            .chunk_size(1)
+            .concurrency(0)
+            .read_ahead(0);
+        assert_eq!(options.chunk_size, MIN_CHUNK_SIZE);
+        assert_eq!(options.concurrency, 1);
+        assert_eq!(options.read_ahead, 1);
+        let options = TransferOptions::default().chunk_size(usize::MAX);
+        assert_eq!(options.chunk_size, MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn should_pull_and_push_in_order() {
+        let data = payload(MIN_CHUNK_SIZE * 5 + 17);
+        let mut dest = Vec::new();
+        assert_eq!(
+            pull(
+                &mut Cursor::new(&data),
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(dest, data);
+        let mut dest = Vec::new();
+        assert_eq!(
+            push(
+                &mut Cursor::new(&data),
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(dest, data);
+    }
+
+    #[test]
+    fn should_abort_cancelled_transfer() {
+        let data = payload(MIN_CHUNK_SIZE * 5);
+        let token = CancellationToken::default();
+        let mut progress = Progress::new(None, token.clone(), Some(data.len() as u64));
+        token.cancel();
+        let mut dest = Vec::new();
+        assert_eq!(
+            pull(
+                &mut Cursor::new(&data),
+                &mut dest,
+                &options(),
+                &mut progress
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::Interrupted
+        );
+        assert!(dest.is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+//! # progress
+//!
+//! Transfer progress reporting and cancellation
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Callback invoked by `SmbFs` each time a chunk has been transferred
+pub type ProgressCallback = Arc<dyn Fn(&TransferProgress) + Send + Sync>;
+
+/// Progress of a running transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferProgress {
+    /// Bytes transferred so far
+    pub transferred: u64,
+    /// Total bytes to transfer, if known
+    pub total: Option<u64>,
+    /// Average throughput in bytes per second
+    pub throughput: f64,
+    /// Estimated time left, if the total is known
+    pub eta: Option<Duration>,
+}
+
+/// Token used to cancel the transfer running on a `SmbFs`.
+///
+/// Cancelling the token aborts the running transfer at the next chunk, closing the remote handle;
+/// the aborted transfer returns an error and the token is reset.
+/// A cancellation requested while no transfer is running is discarded when the next one starts.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Request the cancellation of the running transfer
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether a cancellation has been requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Take the cancellation request, resetting the token
+    fn take(&self) -> bool {
+        self.cancelled.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Tracks the progress of a single transfer
+pub(crate) struct Progress {
+    callback: Option<ProgressCallback>,
+    token: CancellationToken,
+    total: Option<u64>,
+    transferred: u64,
+    started: Instant,
+}
+
+impl Progress {
+    pub fn new(
+        callback: Option<ProgressCallback>,
+        token: CancellationToken,
+        total: Option<u64>,
+    ) -> Self {
+        // a request left over from a finished transfer must not abort this one
+        if token.take() {
+            debug!("discarding cancellation requested while no transfer was running");
+        }
+        Self {
+            callback,
+            token,
+            total,
+            transferred: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Account `bytes` as transferred and report progress.
+    ///
+    /// Fails with `ErrorKind::Interrupted` if the transfer has been cancelled
+    pub fn advance(&mut self, bytes: u64) -> io::Result<()> {
+        if self.token.take() {
+            debug!("transfer cancelled after {} bytes", self.transferred);
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "transfer cancelled",
+            ));
+        }
+        self.transferred += bytes;
+        if let Some(callback) = self.callback.as_ref() {
+            callback(&self.snapshot());
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> TransferProgress {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let throughput = match elapsed > 0.0 {
+            true => self.transferred as f64 / elapsed,
+            false => 0.0,
+        };
+        let eta = self.total.filter(|_| throughput > 0.0).map(|total| {
+            Duration::from_secs_f64(total.saturating_sub(self.transferred) as f64 / throughput)
+        });
+        TransferProgress {
+            transferred: self.transferred,
+            total: self.total,
+            throughput,
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::Mutex;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_report_progress() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let callback: ProgressCallback =
+            Arc::new(move |progress| sink.lock().unwrap().push(*progress));
+        let mut progress = Progress::new(Some(callback), CancellationToken::default(), Some(10));
+        assert!(progress.advance(4).is_ok());
+        assert!(progress.advance(6).is_ok());
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].transferred, 4);
+        assert_eq!(reports[1].transferred, 10);
+        assert_eq!(reports[1].total, Some(10));
+    }
+
+    #[test]
+    fn should_cancel_and_reset_token() {
+        let token = CancellationToken::default();
+        let mut progress = Progress::new(None, token.clone(), None);
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(
+            progress.advance(1).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        assert!(!token.is_cancelled());
+        assert!(progress.advance(1).is_ok());
+    }
+
+    #[test]
+    fn should_discard_cancellation_before_transfer() {
+        let token = CancellationToken::default();
+        token.cancel();
+        let mut progress = Progress::new(None, token.clone(), None);
+        assert!(!token.is_cancelled());
+        assert!(progress.advance(1).is_ok());
+    }
+}
@@ -0,0 +1,533 @@
+//! # ranges
+//!
+//! Parallel ranged transfers, for backends whose handles can be used from multiple threads
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use super::{is_zero, join, read_chunk, Progress, TransferOptions};
+
+/// A handle which can be read at an arbitrary offset without sharing a cursor
+pub trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+/// A handle which can be written at an arbitrary offset without sharing a cursor
+pub trait WriteAt {
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize>;
+}
+
+#[cfg(target_family = "windows")]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+#[cfg(target_family = "windows")]
+impl WriteAt for std::fs::File {
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_write(self, buf, offset)
+    }
+}
+
+/// Download the bytes between `offset` and `size` splitting them into ranges of `chunk_size`,
+/// read by `concurrency` workers each one with its own handle opened with `open`.
+///
+/// Ranges are written into `writer` in order; workers never run more than `2 * concurrency`
+/// chunks ahead of the writer.
+pub fn download_ranges<F, H, W>(
+    open: F,
+    offset: u64,
+    size: u64,
+    writer: &mut W,
+    options: &TransferOptions,
+    progress: &mut Progress,
+) -> io::Result<u64>
+where
+    F: Fn() -> io::Result<H> + Sync,
+    H: ReadAt,
+    W: Write + ?Sized,
+{
+    let chunk_size = options.chunk_size as u64;
+    let base = offset.min(size);
+    let chunks = (size - base).div_ceil(chunk_size);
+    let window = (options.concurrency * 2) as u64;
+    let next = AtomicU64::new(0);
+    let aborted = AtomicBool::new(false);
+    // index of the next chunk to write
+    let written = (Mutex::new(0u64), Condvar::new());
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel::<io::Result<(u64, Vec<u8>)>>();
+        let (open, next, aborted, written) = (&open, &next, &aborted, &written);
+        for _ in 0..(options.concurrency as u64).min(chunks) {
+            let tx = tx.clone();
+            scope.spawn(move || {
+                let handle = match open() {
+                    Ok(handle) => handle,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                };
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= chunks {
+                        return;
+                    }
+                    let (lock, cvar) = written;
+                    let mut guard = lock.lock().unwrap();
+                    while index >= *guard + window && !aborted.load(Ordering::SeqCst) {
+                        guard = cvar.wait(guard).unwrap();
+                    }
+                    drop(guard);
+                    if aborted.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let offset = base + index * chunk_size;
+                    let mut buf = vec![0; chunk_size.min(size - offset) as usize];
+                    let result = read_exact_at(&handle, &mut buf, offset).map(|_| (index, buf));
+                    let failed = result.is_err();
+                    if tx.send(result).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let abort = || {
+            aborted.store(true, Ordering::SeqCst);
+            written.1.notify_all();
+        };
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for message in rx.iter() {
+            let (index, buf) = match message {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    abort();
+                    return Err(err);
+                }
+            };
+            pending.insert(index, buf);
+            while let Some(buf) = pending.remove(&expected) {
+                if let Err(err) = writer
+                    .write_all(&buf)
+                    .and_then(|_| progress.advance(buf.len() as u64))
+                {
+                    abort();
+                    return Err(err);
+                }
+                expected += 1;
+                *written.0.lock().unwrap() = expected;
+                written.1.notify_all();
+            }
+        }
+        if expected < chunks {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "transfer workers terminated early",
+            ));
+        }
+        writer.flush()?;
+        Ok(size - base)
+    })
+}
+
+/// Upload `reader` at `base` splitting it into chunks of `chunk_size`, written by `concurrency`
+/// workers each one with its own handle opened with `open`.
+///
+/// If `sparse` is enabled, the chunks made only of zeros are skipped; a hole at the end of the
+/// file is closed by writing its last byte.
+///
+/// Returns the amount of bytes written.
+pub fn upload_ranges<F, H, R>(
+    open: F,
+    base: u64,
+    reader: &mut R,
+    options: &TransferOptions,
+    progress: &mut Progress,
+) -> io::Result<u64>
+where
+    F: Fn() -> io::Result<H> + Sync,
+    H: WriteAt,
+    R: Read + ?Sized,
+{
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::sync_channel::<(u64, Vec<u8>)>(options.concurrency);
+        // workers own the receiver; once they all quit, `send` fails and the reader stops
+        let rx = Arc::new(Mutex::new(rx));
+        let (open, failed) = (&open, &failed);
+        let workers: Vec<_> = (0..options.concurrency)
+            .map(|_| {
+                let rx = rx.clone();
+                scope.spawn(move || -> io::Result<()> {
+                    let result = open().and_then(|handle| loop {
+                        let message = rx.lock().unwrap().recv();
+                        match message {
+                            Ok((offset, buf)) => write_all_at(&handle, &buf, offset)?,
+                            Err(_) => return Ok(()),
+                        }
+                    });
+                    if result.is_err() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    result
+                })
+            })
+            .collect();
+        drop(rx);
+
+        let mut transferred = 0;
+        let mut trailing_hole = false;
+        let result = loop {
+            if failed.load(Ordering::SeqCst) {
+                break Ok(());
+            }
+            let chunk = match read_chunk(reader, options.chunk_size) {
+                Ok(chunk) => chunk,
+                Err(err) => break Err(err),
+            };
+            let len = chunk.len() as u64;
+            if len == 0 {
+                break Ok(());
+            }
+            trailing_hole = options.sparse && is_zero(&chunk);
+            if !trailing_hole && tx.send((base + transferred, chunk)).is_err() {
+                break Ok(());
+            }
+            transferred += len;
+            if let Err(err) = progress.advance(len) {
+                break Err(err);
+            }
+        };
+        drop(tx);
+        // report the first worker failure, if any, before the reader error
+        for worker in workers {
+            join(worker)?;
+        }
+        result?;
+        if trailing_hole {
+            write_all_at(&open()?, &[0], base + transferred - 1)?;
+        }
+        Ok(transferred)
+    })
+}
+
+fn read_exact_at<H: ReadAt>(handle: &H, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match handle.read_at(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file is shorter than expected",
+                ))
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn write_all_at<H: WriteAt>(handle: &H, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match handle.write_at(buf, offset) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+    use std::ops::Range;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::transfer::test::{options, payload, progress};
+    use crate::transfer::MIN_CHUNK_SIZE;
+
+    struct MemFile(Arc<Mutex<Vec<u8>>>);
+
+    impl ReadAt for MemFile {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            let data = self.0.lock().unwrap();
+            let offset = (offset as usize).min(data.len());
+            let n = buf.len().min(data.len() - offset);
+            buf[..n].copy_from_slice(&data[offset..offset + n]);
+            Ok(n)
+        }
+    }
+
+    impl WriteAt for MemFile {
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            let mut data = self.0.lock().unwrap();
+            let end = offset as usize + buf.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[offset as usize..end].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// `MemFile` recording the ranges read and written through it
+    struct Recorder {
+        file: MemFile,
+        ranges: Arc<Mutex<Vec<Range<u64>>>>,
+    }
+
+    impl ReadAt for Recorder {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            let n = self.file.read_at(buf, offset)?;
+            self.ranges
+                .lock()
+                .unwrap()
+                .push(offset..offset + buf.len() as u64);
+            Ok(n)
+        }
+    }
+
+    impl WriteAt for Recorder {
+        fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            self.ranges
+                .lock()
+                .unwrap()
+                .push(offset..offset + buf.len() as u64);
+            self.file.write_at(buf, offset)
+        }
+    }
+
+    fn sorted(ranges: &Mutex<Vec<Range<u64>>>) -> Vec<Range<u64>> {
+        let mut ranges = ranges.lock().unwrap().clone();
+        ranges.sort_by_key(|range| range.start);
+        ranges
+    }
+
+    #[test]
+    fn should_split_download_into_chunks() {
+        let chunk = MIN_CHUNK_SIZE as u64;
+        let size = 100 + chunk * 3 + 5;
+        let remote = Arc::new(Mutex::new(payload(size as usize)));
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let mut dest = Vec::new();
+        assert_eq!(
+            download_ranges(
+                || {
+                    Ok(Recorder {
+                        file: MemFile(remote.clone()),
+                        ranges: ranges.clone(),
+                    })
+                },
+                100,
+                size,
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            size - 100
+        );
+        // the ranges start from the offset; the last one is shorter
+        assert_eq!(
+            sorted(&ranges),
+            vec![
+                100..100 + chunk,
+                100 + chunk..100 + chunk * 2,
+                100 + chunk * 2..100 + chunk * 3,
+                100 + chunk * 3..size,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_download_past_the_end() {
+        let mut dest = Vec::new();
+        assert_eq!(
+            download_ranges(
+                || -> io::Result<MemFile> { panic!("handle opened") },
+                20,
+                10,
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            0
+        );
+        assert!(dest.is_empty());
+    }
+
+    #[test]
+    fn should_split_upload_into_chunks() {
+        let chunk = MIN_CHUNK_SIZE as u64;
+        let mut data = payload(MIN_CHUNK_SIZE * 3 + 7);
+        // the trailing hole is closed by its last byte
+        data[MIN_CHUNK_SIZE * 3..].fill(0);
+        let remote = Arc::new(Mutex::new(Vec::new()));
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        assert_eq!(
+            upload_ranges(
+                || {
+                    Ok(Recorder {
+                        file: MemFile(remote.clone()),
+                        ranges: ranges.clone(),
+                    })
+                },
+                4,
+                &mut Cursor::new(&data),
+                &options().sparse(true),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        let end = 4 + data.len() as u64;
+        assert_eq!(
+            sorted(&ranges),
+            vec![
+                4..4 + chunk,
+                4 + chunk..4 + chunk * 2,
+                4 + chunk * 2..4 + chunk * 3,
+                end - 1..end,
+            ]
+        );
+        assert_eq!(remote.lock().unwrap().len() as u64, end);
+    }
+
+    #[test]
+    fn should_download_ranges_in_order() {
+        let data = payload(MIN_CHUNK_SIZE * 9 + 3);
+        let remote = Arc::new(Mutex::new(data.clone()));
+        let mut dest = Vec::new();
+        assert_eq!(
+            download_ranges(
+                || Ok(MemFile(remote.clone())),
+                0,
+                data.len() as u64,
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(dest, data);
+    }
+
+    #[test]
+    fn should_fail_download_if_file_is_shorter() {
+        let remote = Arc::new(Mutex::new(payload(10)));
+        let mut dest = Vec::new();
+        assert!(download_ranges(
+            || Ok(MemFile(remote.clone())),
+            0,
+            20,
+            &mut dest,
+            &options(),
+            &mut progress()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn should_download_ranges_from_offset() {
+        let data = payload(MIN_CHUNK_SIZE * 3 + 5);
+        let remote = Arc::new(Mutex::new(data.clone()));
+        let mut dest = Vec::new();
+        assert_eq!(
+            download_ranges(
+                || Ok(MemFile(remote.clone())),
+                100,
+                data.len() as u64,
+                &mut dest,
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64 - 100
+        );
+        assert_eq!(dest.as_slice(), &data[100..]);
+    }
+
+    #[test]
+    fn should_upload_ranges_at_offset() {
+        let data = payload(MIN_CHUNK_SIZE * 7 + 1);
+        let remote = Arc::new(Mutex::new(vec![0xff; 4]));
+        assert_eq!(
+            upload_ranges(
+                || Ok(MemFile(remote.clone())),
+                4,
+                &mut Cursor::new(&data),
+                &options(),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        let remote = remote.lock().unwrap();
+        assert_eq!(&remote[..4], &[0xff; 4]);
+        assert_eq!(&remote[4..], data.as_slice());
+    }
+
+    #[test]
+    fn should_skip_zero_chunks_in_sparse_upload() {
+        let mut data = payload(MIN_CHUNK_SIZE * 4);
+        data[MIN_CHUNK_SIZE..MIN_CHUNK_SIZE * 2].fill(0);
+        data[MIN_CHUNK_SIZE * 3..].fill(0);
+        // the skipped chunks must not overwrite the remote content
+        let remote = Arc::new(Mutex::new(vec![0xff; MIN_CHUNK_SIZE * 2]));
+        assert_eq!(
+            upload_ranges(
+                || Ok(MemFile(remote.clone())),
+                0,
+                &mut Cursor::new(&data),
+                &options().sparse(true),
+                &mut progress()
+            )
+            .unwrap(),
+            data.len() as u64
+        );
+        let remote = remote.lock().unwrap();
+        assert_eq!(remote.len(), data.len());
+        assert_eq!(&remote[..MIN_CHUNK_SIZE], &data[..MIN_CHUNK_SIZE]);
+        assert!(remote[MIN_CHUNK_SIZE..MIN_CHUNK_SIZE * 2]
+            .iter()
+            .all(|byte| *byte == 0xff));
+        assert_eq!(&remote[MIN_CHUNK_SIZE * 2..], &data[MIN_CHUNK_SIZE * 2..]);
+    }
+
+    #[test]
+    fn should_report_upload_open_failure() {
+        let data = payload(MIN_CHUNK_SIZE * 20);
+        let result = upload_ranges(
+            || -> io::Result<MemFile> { Err(io::Error::from(io::ErrorKind::PermissionDenied)) },
+            0,
+            &mut Cursor::new(&data),
+            &options(),
+            &mut progress(),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,291 @@
+//! # reopen
+//!
+//! Writers which resume on a new handle after the loss of the connection
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+/// Delay before reopening a handle, multiplied by the amount of failed attempts
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
+
+/// Writer which reopens its handle when a write, a flush or a seek fails because the connection
+/// has been lost, then retries it at the same offset.
+///
+/// `reopen` gets the amount of bytes written or skipped so far and must return a handle
+/// positioned right after them. Up to `attempts` reopens are tried in a row before giving up.
+pub struct Reopening<W, F> {
+    inner: W,
+    reopen: F,
+    written: u64,
+    attempts: usize,
+    delay: Duration,
+}
+
+impl<W, F> Reopening<W, F>
+where
+    W: Write,
+    F: FnMut(u64) -> io::Result<W>,
+{
+    pub fn new(inner: W, reopen: F, attempts: usize) -> Self {
+        Self {
+            inner,
+            reopen,
+            written: 0,
+            attempts,
+            delay: REOPEN_DELAY,
+        }
+    }
+
+    /// Run `op` on the handle, reopening it and running `op` again while it fails because the
+    /// connection has been lost
+    fn retry<T>(&mut self, mut op: impl FnMut(&mut W) -> io::Result<T>) -> io::Result<T> {
+        let mut failures = 0;
+        loop {
+            let mut err = match op(&mut self.inner) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            // reopen the handle until it succeeds; a failed reopen counts as a failed attempt too
+            loop {
+                if failures >= self.attempts || !is_connection_lost(&err) {
+                    return Err(err);
+                }
+                failures += 1;
+                warn!(
+                    "connection lost after writing {} bytes ({}); reopening the handle",
+                    self.written, err
+                );
+                std::thread::sleep(self.delay * failures as u32);
+                match (self.reopen)(self.written) {
+                    Ok(inner) => {
+                        self.inner = inner;
+                        break;
+                    }
+                    Err(reopen_err) => err = reopen_err,
+                }
+            }
+        }
+    }
+}
+
+impl<W, F> Write for Reopening<W, F>
+where
+    W: Write,
+    F: FnMut(u64) -> io::Result<W>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.retry(|inner| inner.write(buf))?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.retry(|inner| inner.flush())
+    }
+}
+
+impl<W, F> Seek for Reopening<W, F>
+where
+    W: Write + Seek,
+    F: FnMut(u64) -> io::Result<W>,
+{
+    /// Only relative seeks are supported, as the handle is reopened relatively to its initial
+    /// offset
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let SeekFrom::Current(delta) = pos else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only relative seeks are supported",
+            ));
+        };
+        let written = self.written.checked_add_signed(delta).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the initial offset",
+            )
+        })?;
+        let position = self.retry(|inner| inner.seek(pos))?;
+        self.written = written;
+        Ok(position)
+    }
+}
+
+/// Whether `err` is caused by the loss of the connection
+fn is_connection_lost(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::transfer::sparse::Sparse;
+
+    /// Writer which fails with `err` after accepting `capacity` bytes, and on flush if `flaky_flush`
+    struct Flaky {
+        data: Vec<u8>,
+        capacity: usize,
+        err: io::ErrorKind,
+        flaky_flush: bool,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.data.len() + buf.len() > self.capacity {
+                return Err(io::Error::from(self.err));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.flaky_flush {
+                return Err(io::Error::from(self.err));
+            }
+            Ok(())
+        }
+    }
+
+    impl Seek for Flaky {
+        /// Fill the gap with zeros, as a file would read them back
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let SeekFrom::Current(delta) = pos else {
+                unimplemented!()
+            };
+            self.data.resize(self.data.len() + delta as usize, 0);
+            Ok(self.data.len() as u64)
+        }
+    }
+
+    fn flaky(capacity: usize, err: io::ErrorKind) -> Flaky {
+        Flaky {
+            data: Vec::new(),
+            capacity,
+            err,
+            flaky_flush: false,
+        }
+    }
+
+    #[test]
+    fn should_reopen_handle_when_connection_is_lost() {
+        let mut offsets = Vec::new();
+        let mut writer = Reopening::new(
+            flaky(4, io::ErrorKind::ConnectionReset),
+            |offset| {
+                offsets.push(offset);
+                Ok(flaky(usize::MAX, io::ErrorKind::ConnectionReset))
+            },
+            3,
+        );
+        writer.delay = Duration::ZERO;
+        writer.write_all(b"abcd").unwrap();
+        writer.write_all(b"efgh").unwrap();
+        assert_eq!(writer.inner.data, b"efgh");
+        assert_eq!(writer.written, 8);
+        drop(writer);
+        assert_eq!(offsets, vec![4]);
+    }
+
+    #[test]
+    fn should_reopen_handle_when_flush_fails() {
+        let mut offsets = Vec::new();
+        let mut first = flaky(usize::MAX, io::ErrorKind::NotConnected);
+        first.flaky_flush = true;
+        let mut writer = Reopening::new(
+            first,
+            |offset| {
+                offsets.push(offset);
+                Ok(flaky(usize::MAX, io::ErrorKind::NotConnected))
+            },
+            3,
+        );
+        writer.delay = Duration::ZERO;
+        writer.write_all(b"abcd").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(offsets, vec![4]);
+    }
+
+    #[test]
+    fn should_close_trailing_hole_after_reopen() {
+        let mut offsets = Vec::new();
+        let mut writer = Reopening::new(
+            flaky(2, io::ErrorKind::ConnectionReset),
+            |offset| {
+                offsets.push(offset);
+                Ok(flaky(usize::MAX, io::ErrorKind::ConnectionReset))
+            },
+            3,
+        );
+        writer.delay = Duration::ZERO;
+        let mut writer = Sparse::new(writer, true);
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(&[0; 4]).unwrap();
+        // the last byte of the hole, written on flush, is lost with the connection
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(offsets, vec![5]);
+    }
+
+    #[test]
+    fn should_seek_relatively() {
+        let mut writer = Reopening::new(
+            flaky(usize::MAX, io::ErrorKind::ConnectionReset),
+            |_| -> io::Result<Flaky> { panic!("handle reopened") },
+            3,
+        );
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(2)).unwrap(), 4);
+        assert_eq!(writer.written, 4);
+        assert_eq!(
+            writer.seek(SeekFrom::Start(0)).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            writer.seek(SeekFrom::Current(-5)).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn should_give_up_after_attempts() {
+        let mut reopens = 0;
+        let mut writer = Reopening::new(
+            flaky(0, io::ErrorKind::BrokenPipe),
+            |_| {
+                reopens += 1;
+                Ok(flaky(0, io::ErrorKind::BrokenPipe))
+            },
+            2,
+        );
+        writer.delay = Duration::ZERO;
+        assert_eq!(
+            writer.write(b"a").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        drop(writer);
+        assert_eq!(reopens, 2);
+    }
+
+    #[test]
+    fn should_not_reopen_on_other_errors() {
+        let mut writer = Reopening::new(
+            flaky(0, io::ErrorKind::PermissionDenied),
+            |_| -> io::Result<Flaky> { panic!("handle reopened") },
+            3,
+        );
+        assert_eq!(
+            writer.write(b"a").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+}
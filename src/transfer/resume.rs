@@ -0,0 +1,103 @@
+//! # resume
+//!
+//! Helpers to resume interrupted transfers
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Get the offset to resume a transfer from.
+///
+/// `partial_size` is the size of what has already been transferred into the destination.
+/// If the partial destination is larger than the source or, when `verify_tail` is not zero,
+/// the last `verify_tail` bytes of the partial destination don't match the same range of the source,
+/// the transfer must start over and `0` is returned.
+pub fn resume_offset<S, D>(
+    source: &mut S,
+    source_size: u64,
+    partial: &mut D,
+    partial_size: u64,
+    verify_tail: usize,
+) -> io::Result<u64>
+where
+    S: Read + Seek + ?Sized,
+    D: Read + Seek + ?Sized,
+{
+    if partial_size > source_size {
+        debug!(
+            "destination is larger than source ({} > {}); starting over",
+            partial_size, source_size
+        );
+        return Ok(0);
+    }
+    let tail = (verify_tail as u64).min(partial_size);
+    if tail > 0 {
+        let offset = partial_size - tail;
+        if read_range(source, offset, tail)? != read_range(partial, offset, tail)? {
+            debug!("tail of the destination doesn't match the source; starting over");
+            return Ok(0);
+        }
+    }
+    Ok(partial_size)
+}
+
+fn read_range<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    offset: u64,
+    len: u64,
+) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_resume_from_partial_size() {
+        let mut source = Cursor::new(b"hello, world!".to_vec());
+        let mut partial = Cursor::new(b"hello".to_vec());
+        assert_eq!(
+            resume_offset(&mut source, 13, &mut partial, 5, 0).unwrap(),
+            5
+        );
+        assert_eq!(
+            resume_offset(&mut source, 13, &mut partial, 5, 3).unwrap(),
+            5
+        );
+        assert_eq!(
+            resume_offset(&mut source, 13, &mut partial, 5, 1024).unwrap(),
+            5
+        );
+    }
+
+    #[test]
+    fn should_start_over_if_tail_differs() {
+        let mut source = Cursor::new(b"hello, world!".to_vec());
+        let mut partial = Cursor::new(b"hellO".to_vec());
+        assert_eq!(
+            resume_offset(&mut source, 13, &mut partial, 5, 0).unwrap(),
+            5
+        );
+        assert_eq!(
+            resume_offset(&mut source, 13, &mut partial, 5, 2).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn should_start_over_if_destination_is_larger() {
+        let mut source = Cursor::new(b"hello".to_vec());
+        let mut partial = Cursor::new(b"hello, world!".to_vec());
+        assert_eq!(
+            resume_offset(&mut source, 5, &mut partial, 13, 0).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! # sparse
+//!
+//! Writers leaving holes in place of the zeros
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::is_zero;
+
+/// Writer which, when enabled, seeks over the buffers made only of zeros instead of writing them,
+/// so that the server can leave holes in their place.
+///
+/// A hole at the end of the file is closed by writing its last byte on `flush`, to give the
+/// file its full size.
+pub struct Sparse<W> {
+    inner: W,
+    enabled: bool,
+    hole: u64,
+}
+
+impl<W> Sparse<W>
+where
+    W: Write + Seek,
+{
+    pub fn new(inner: W, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            hole: 0,
+        }
+    }
+
+    /// Move the cursor of the inner writer past the pending hole
+    fn skip_hole(&mut self) -> io::Result<()> {
+        if self.hole > 0 {
+            self.inner.seek(SeekFrom::Current(self.hole as i64))?;
+            self.hole = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Write for Sparse<W>
+where
+    W: Write + Seek,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.enabled && is_zero(buf) {
+            self.hole += buf.len() as u64;
+            return Ok(buf.len());
+        }
+        self.skip_hole()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.hole > 0 {
+            self.hole -= 1;
+            self.skip_hole()?;
+            self.inner.write_all(&[0])?;
+        }
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_skip_zeros() {
+        let mut writer = Sparse::new(Cursor::new(Vec::new()), true);
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(&[0; 4]).unwrap();
+        writer.write_all(b"cd").unwrap();
+        assert_eq!(writer.inner.get_ref(), b"ab\0\0\0\0cd");
+        // the cursor fills the gap with zeros; a remote file would rather leave a hole
+        writer.write_all(&[0; 3]).unwrap();
+        assert_eq!(writer.inner.get_ref().len(), 8);
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.get_ref(), b"ab\0\0\0\0cd\0\0\0");
+    }
+
+    #[test]
+    fn should_write_zeros_when_disabled() {
+        let mut writer = Sparse::new(Cursor::new(Vec::new()), false);
+        writer.write_all(&[0; 4]).unwrap();
+        assert_eq!(writer.inner.get_ref(), &[0; 4]);
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.get_ref(), &[0; 4]);
+    }
+}
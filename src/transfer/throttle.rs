@@ -0,0 +1,212 @@
+//! # throttle
+//!
+//! Bandwidth throttling shared by all the handles of a client
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use remotefs::fs::stream::{ReadAndSeek, WriteAndSeek};
+
+#[cfg(any(target_family = "windows", test))]
+use super::ranges::{ReadAt, WriteAt};
+
+/// Token bucket limiting the bandwidth used by a `SmbFs`.
+///
+/// The limiter is cheap to clone and all the clones share the same bucket, so the limit
+/// can be changed at runtime with [`RateLimiter::set_limit`] and applies to every transfer
+/// and stream of the client. Unlimited by default.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second; `None` if unlimited
+    rate: Option<u64>,
+    /// Max amount of bytes which can be transferred at once without waiting
+    burst: u64,
+    /// Available bytes; negative if the bucket is in debt
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            rate: None,
+            burst: 0,
+            tokens: 0.0,
+            refilled_at: Instant::now(),
+        }
+    }
+}
+
+impl Bucket {
+    /// Refill the bucket up to `now`, then take `bytes` from it.
+    /// Returns how long to wait before the bytes are allowed by the rate
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        let refill = now.duration_since(self.refilled_at).as_secs_f64() * rate as f64;
+        self.tokens = (self.tokens + refill).min(self.burst as f64);
+        self.refilled_at = now;
+        self.tokens -= bytes as f64;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / rate as f64),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `rate` bytes per second, with bursts up to `burst` bytes
+    pub fn new(rate: u64, burst: u64) -> Self {
+        let limiter = Self::default();
+        limiter.set_limit(Some(rate), burst);
+        limiter
+    }
+
+    /// Change the limit to `rate` bytes per second with bursts up to `burst` bytes.
+    /// `None` removes the limit
+    pub fn set_limit(&self, rate: Option<u64>, burst: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.rate = rate.filter(|rate| *rate > 0);
+        bucket.burst = burst;
+        bucket.tokens = burst as f64;
+        bucket.refilled_at = Instant::now();
+    }
+
+    /// Get the current limit in bytes per second, if any
+    pub fn limit(&self) -> Option<u64> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Take `bytes` from the bucket, sleeping until the rate allows them
+    pub(crate) fn acquire(&self, bytes: u64) {
+        let wait = self.bucket.lock().unwrap().take(bytes, Instant::now());
+        if !wait.is_zero() {
+            trace!("throttling for {:?}", wait);
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Wraps a handle, accounting each read and write in a [`RateLimiter`]
+pub(crate) struct Throttled<T> {
+    inner: T,
+    limiter: RateLimiter,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, limiter: RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<T: Read> Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.inner.read(buf)?;
+        self.limiter.acquire(bytes as u64);
+        Ok(bytes)
+    }
+}
+
+impl<T: Write> Write for Throttled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.inner.write(buf)?;
+        self.limiter.acquire(bytes as u64);
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for Throttled<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<T: ReadAndSeek> ReadAndSeek for Throttled<T> {}
+
+impl<T: WriteAndSeek> WriteAndSeek for Throttled<T> {}
+
+#[cfg(any(target_family = "windows", test))]
+impl<T: ReadAt> ReadAt for Throttled<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.inner.read_at(buf, offset)?;
+        self.limiter.acquire(bytes as u64);
+        Ok(bytes)
+    }
+}
+
+#[cfg(any(target_family = "windows", test))]
+impl<T: WriteAt> WriteAt for Throttled<T> {
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let bytes = self.inner.write_at(buf, offset)?;
+        self.limiter.acquire(bytes as u64);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn should_not_throttle_when_unlimited() {
+        let limiter = RateLimiter::default();
+        assert_eq!(limiter.limit(), None);
+        let mut bucket = limiter.bucket.lock().unwrap();
+        let now = bucket.refilled_at;
+        assert_eq!(bucket.take(1024 * 1024, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn should_throttle_to_rate() {
+        let limiter = RateLimiter::new(100_000, 10_000);
+        assert_eq!(limiter.limit(), Some(100_000));
+        let mut bucket = limiter.bucket.lock().unwrap();
+        let start = bucket.refilled_at;
+        // the burst is free, the remaining 30_000 bytes take 300ms
+        assert_eq!(bucket.take(10_000, start), Duration::ZERO);
+        assert_eq!(bucket.take(30_000, start), Duration::from_millis(300));
+        // once the debt has been paid, the bucket refills up to the burst only
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(10_000, later), Duration::ZERO);
+        assert_eq!(bucket.take(1_000, later), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn should_lift_limit_at_runtime() {
+        let limiter = RateLimiter::new(100_000, 0);
+        let mut writer = Throttled::new(Vec::new(), limiter.clone());
+        limiter.set_limit(None, 0);
+        assert_eq!(limiter.limit(), None);
+        // with the limit still in place, this would take 10 seconds
+        writer.write_all(&[0; 1_000_000]).unwrap();
+        assert_eq!(writer.inner.len(), 1_000_000);
+    }
+
+    #[test]
+    fn should_account_reads_and_writes() {
+        // refilling a single token per second, so that the elapsed time doesn't matter
+        let limiter = RateLimiter::new(1, 1_000);
+        let mut reader = Throttled::new(Cursor::new(vec![0; 600]), limiter.clone());
+        assert_eq!(io::copy(&mut reader, &mut io::sink()).unwrap(), 600);
+        let mut writer = Throttled::new(Vec::new(), limiter.clone());
+        writer.write_all(&[0; 300]).unwrap();
+        let tokens = limiter.bucket.lock().unwrap().tokens;
+        assert!((100.0..101.0).contains(&tokens));
+    }
+}
@@ -2,13 +2,41 @@
 //!
 //! SMB protocol utilities
 
+#[cfg(any(smbc_configuration, smbc_protocols))]
+use std::ffi::c_char;
+#[cfg(smbc_configuration)]
+use std::ffi::c_int;
+#[cfg(smbc_protocols)]
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::io;
+#[cfg(smbc_configuration)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(smbc_configuration)]
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use libc::mode_t;
-use pavao::SmbStat;
+use pavao::{SmbClient, SmbDirentInfo, SmbDirentType, SmbEncryptionLevel, SmbStat};
+#[cfg(any(smbc_posix_extensions, smbc_protocols))]
+use pavao_sys::smbc_bool;
+#[cfg(any(smbc_posix_extensions, smbc_configuration, smbc_protocols))]
+use pavao_sys::SMBCCTX;
+use pavao_sys::{
+    smbc_getFunctionClosedir, smbc_getFunctionOpendir, smbc_getFunctionReaddirPlus,
+    smbc_getFunctionStat, smbc_setOptionSmbEncryptionLevel, SMBCFILE,
+};
 use remotefs::fs::{FileType, Metadata, UnixPex};
 use remotefs::File;
 
+use crate::cache::ContentVersion;
+#[cfg(smbc_protocols)]
+use crate::dialect::SmbDialect;
+
+/// DOS attribute of read-only entries
+const FILE_ATTRIBUTE_READONLY: u16 = 0x01;
+
 /// Convert `SmbStat` to `File`
 pub fn smbstat_to_file<S: AsRef<str>>(uri: S, stat: SmbStat) -> File {
     #[cfg(target_os = "macos")]
@@ -37,3 +65,262 @@ fn get_file_type_from_stat(stat: &SmbStat) -> FileType {
         _ => FileType::File,
     }
 }
+
+// Functions of libsmbclient pavao-sys doesn't bind, as declared by `libsmbclient.h`;
+// build.rs sets the cfg of each one only if the header of the linked libsmbclient declares it
+extern "C" {
+    #[cfg(smbc_posix_extensions)]
+    fn smbc_setOptionPosixExtensions(c: *mut SMBCCTX, v: smbc_bool);
+    #[cfg(smbc_configuration)]
+    fn smbc_setConfiguration(c: *mut SMBCCTX, file: *const c_char) -> c_int;
+    #[cfg(smbc_protocols)]
+    fn smbc_setOptionProtocols(
+        c: *mut SMBCCTX,
+        min_proto: *const c_char,
+        max_proto: *const c_char,
+    ) -> smbc_bool;
+}
+
+/// Enable or disable the SMB3.1.1 POSIX extensions on the context of `client`
+#[cfg(smbc_posix_extensions)]
+pub fn set_posix_extensions(client: &SmbClient, enable: bool) -> io::Result<()> {
+    let ctx = client.ctx().map_err(io::Error::other)?;
+    unsafe { smbc_setOptionPosixExtensions(ctx, smbc_bool::from(enable)) };
+    Ok(())
+}
+
+/// Set the range of dialects libsmbclient negotiates; `None` leaves the bound unchanged
+#[cfg(smbc_protocols)]
+pub fn set_protocols(
+    client: &SmbClient,
+    min: Option<SmbDialect>,
+    max: Option<SmbDialect>,
+) -> io::Result<()> {
+    let ctx = client.ctx().map_err(io::Error::other)?;
+    let min = min
+        .map(|dialect| CString::new(dialect.samba_name()))
+        .transpose()?;
+    let max = max
+        .map(|dialect| CString::new(dialect.samba_name()))
+        .transpose()?;
+    match unsafe {
+        smbc_setOptionProtocols(
+            ctx,
+            min.as_deref().map_or(std::ptr::null(), CStr::as_ptr),
+            max.as_deref().map_or(std::ptr::null(), CStr::as_ptr),
+        )
+    } {
+        0 => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "libsmbclient rejected the protocol range",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Set the encryption level of the sessions libsmbclient establishes from now on
+pub fn set_encryption_level(client: &SmbClient, level: SmbEncryptionLevel) -> io::Result<()> {
+    let ctx = client.ctx().map_err(io::Error::other)?;
+    unsafe { smbc_setOptionSmbEncryptionLevel(ctx, level.into()) };
+    Ok(())
+}
+
+/// Load the global parameters of libsmbclient from the `smb.conf` file at `path`
+#[cfg(smbc_configuration)]
+pub fn set_configuration(client: &SmbClient, path: &Path) -> io::Result<()> {
+    let ctx = client.ctx().map_err(io::Error::other)?;
+    let file = CString::new(path.as_os_str().as_bytes())?;
+    match unsafe { smbc_setConfiguration(ctx, file.as_ptr()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("libsmbclient could not load {}", path.display()),
+        )),
+    }
+}
+
+/// Stat the entry at `uri`, a full `smb://` URI, keeping the fields `SmbStat` drops (`st_ino`)
+pub fn raw_stat(client: &SmbClient, uri: &str) -> io::Result<libc::stat> {
+    let ctx = client.ctx().map_err(io::Error::other)?;
+    let uri = CString::new(uri)?;
+    let stat_fn = unsafe { smbc_getFunctionStat(ctx) }
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    match stat_fn(ctx, uri.as_ptr(), &mut stat) {
+        0 => Ok(stat),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Get the version of the content of the file described by `stat`, to validate the data cache
+#[allow(clippy::unnecessary_cast)] // the types of the `stat` fields differ across platforms
+pub fn content_version(stat: &libc::stat) -> ContentVersion {
+    ContentVersion::new(
+        stat.st_size as u64,
+        stat_time(stat.st_mtime as i64, stat.st_mtime_nsec as i64),
+    )
+    .changed(Some(stat_time(
+        stat.st_ctime as i64,
+        stat.st_ctime_nsec as i64,
+    )))
+    .file_id(Some(stat.st_ino as u64))
+}
+
+/// Convert a time of `stat` to `SystemTime`
+fn stat_time(secs: i64, nsecs: i64) -> SystemTime {
+    let since_epoch = Duration::new(secs.unsigned_abs(), nsecs.clamp(0, 999_999_999) as u32);
+    match secs < 0 {
+        true => SystemTime::UNIX_EPOCH - since_epoch,
+        false => SystemTime::UNIX_EPOCH + since_epoch,
+    }
+}
+
+/// Directory opened through the context of a client, read one entry at a time and closed on drop
+pub struct Directory<'a> {
+    client: &'a SmbClient,
+    fd: *mut SMBCFILE,
+}
+
+impl<'a> Directory<'a> {
+    /// Open the directory at `uri`, a full `smb://` URI
+    pub fn open(client: &'a SmbClient, uri: &str) -> io::Result<Self> {
+        let ctx = client.ctx().map_err(io::Error::other)?;
+        let uri = CString::new(uri)?;
+        let opendir = unsafe { smbc_getFunctionOpendir(ctx) }
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let fd = opendir(ctx, uri.as_ptr());
+        match fd.is_null() {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(Self { client, fd }),
+        }
+    }
+}
+
+impl Iterator for Directory<'_> {
+    type Item = SmbDirentInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ctx = self.client.ctx().ok()?;
+        let readdirplus = unsafe { smbc_getFunctionReaddirPlus(ctx) }?;
+        loop {
+            // NULL at the end of the directory
+            let info = readdirplus(ctx, self.fd);
+            if info.is_null() {
+                return None;
+            }
+            match SmbDirentInfo::try_from(unsafe { *info }) {
+                Ok(info)
+                    if info.name() != "." && info.name() != ".." && !info.name().is_empty() =>
+                {
+                    return Some(info)
+                }
+                Ok(_) => {}
+                Err(err) => error!("failed to decode directory entry: {}", err),
+            }
+        }
+    }
+}
+
+impl Drop for Directory<'_> {
+    fn drop(&mut self) {
+        let Ok(ctx) = self.client.ctx() else {
+            return;
+        };
+        if let Some(closedir) = unsafe { smbc_getFunctionClosedir(ctx) } {
+            closedir(ctx, self.fd);
+        }
+    }
+}
+
+/// Convert the `SmbDirentInfo` of an entry of the directory at `dir` to `File`.
+///
+/// Directory listings don't report the mode, so it is derived from the DOS attributes
+pub fn dirent_info_to_file<S: AsRef<str>>(dir: S, info: SmbDirentInfo) -> File {
+    let file_type = dirent_file_type(info.get_type());
+    let mode = match file_type {
+        FileType::Directory => 0o755,
+        FileType::File => 0o644,
+        FileType::Symlink => 0o777,
+    };
+    let mode = match info.attrs & FILE_ATTRIBUTE_READONLY {
+        0 => mode,
+        _ => mode & !0o222,
+    };
+
+    File {
+        path: PathBuf::from(format!(
+            "{}/{}",
+            dir.as_ref().trim_end_matches('/'),
+            info.name()
+        )),
+        metadata: Metadata::default()
+            .accessed(info.atime)
+            .created(info.btime)
+            .file_type(file_type)
+            .gid(info.gid)
+            .mode(UnixPex::from(mode))
+            .modified(info.mtime)
+            .size(info.size)
+            .uid(info.uid),
+    }
+}
+
+/// Get the type of a directory entry of type `dirent_type`.
+///
+/// Listings mostly derive the type out of the DOS attributes, so symlinks are reported as `Link`
+/// only when libsmbclient flags them so; `stat` tells them apart when the server grants the POSIX
+/// extensions. Any other entry which is not a directory is a file
+pub fn dirent_file_type(dirent_type: SmbDirentType) -> FileType {
+    match dirent_type {
+        SmbDirentType::Dir => FileType::Directory,
+        SmbDirentType::Link => FileType::Symlink,
+        _ => FileType::File,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn dirent_info(name: &str, attrs: u16) -> SmbDirentInfo {
+        SmbDirentInfo {
+            name: name.to_string(),
+            short_name: String::new(),
+            size: 8,
+            attrs,
+            ctime: SystemTime::UNIX_EPOCH,
+            btime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            atime: SystemTime::UNIX_EPOCH,
+            uid: 1000,
+            gid: 1000,
+        }
+    }
+
+    #[test]
+    fn should_convert_dirent_info_to_file() {
+        let file = dirent_info_to_file("/dir/", dirent_info("a.txt", 0x20));
+        assert_eq!(file.path(), Path::new("/dir/a.txt"));
+        assert!(file.is_file());
+        assert_eq!(file.metadata.size, 8);
+        assert_eq!(file.metadata.mode, Some(UnixPex::from(0o644)));
+        let file = dirent_info_to_file("/", dirent_info("sub", 0x10 | FILE_ATTRIBUTE_READONLY));
+        assert_eq!(file.path(), Path::new("/sub"));
+        assert!(file.is_dir());
+        assert_eq!(file.metadata.mode, Some(UnixPex::from(0o555)));
+    }
+
+    #[test]
+    fn should_get_dirent_file_type() {
+        assert_eq!(dirent_file_type(SmbDirentType::Dir), FileType::Directory);
+        assert_eq!(dirent_file_type(SmbDirentType::Link), FileType::Symlink);
+        assert_eq!(dirent_file_type(SmbDirentType::File), FileType::File);
+        assert_eq!(dirent_file_type(SmbDirentType::FileShare), FileType::File);
+    }
+}